@@ -0,0 +1,49 @@
+use actix_web::{http::header, HttpRequest};
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Keys that other services present as `Authorization: Bearer <key>`.
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    keys: Vec<String>,
+}
+
+impl ApiKeys {
+    /// Reads `SERVICE_API_KEYS`, a comma separated list. With none set, every
+    /// service request is refused.
+    pub fn from_env() -> ApiKeys {
+        let keys = std::env::var("SERVICE_API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .collect();
+        ApiKeys { keys }
+    }
+
+    pub fn authorize(&self, request: &HttpRequest) -> Result<()> {
+        let presented = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        match presented {
+            Some(presented) if self.keys.iter().any(|key| same(key, presented)) => Ok(()),
+            _ => Err(Error::new(
+                "A valid API key is required",
+                ErrorKind::UnAuthorized,
+            )),
+        }
+    }
+}
+
+/// Compares keys in time independent of where they first differ.
+fn same(key: &str, presented: &str) -> bool {
+    key.len() == presented.len()
+        && key
+            .bytes()
+            .zip(presented.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
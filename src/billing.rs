@@ -0,0 +1,397 @@
+use chrono::{Datelike, Duration, FixedOffset, NaiveDate, TimeZone};
+use mongodb::bson::DateTime;
+
+use crate::catalog::PricingCatalog;
+use crate::error::{Error, ErrorKind, Result};
+use crate::metering::{month_key, overage};
+use crate::model::{
+    OrganizationAdditionUsage, OrganizationPricingAdditions, OrganizationPricingTier,
+    OrganizationUsage, UsageRecord,
+};
+use crate::money::{Money, Rounding};
+
+/// Billing dates are reckoned in Indian Standard Time (UTC+05:30).
+pub const IST_OFFSET_SECS: i32 = 5 * 3600 + 30 * 60;
+
+pub fn ist() -> FixedOffset {
+    FixedOffset::east_opt(IST_OFFSET_SECS).unwrap()
+}
+
+/// Calendar date of `dt` in IST.
+pub fn local_date(dt: DateTime) -> NaiveDate {
+    dt.to_chrono().with_timezone(&ist()).date_naive()
+}
+
+/// Midnight IST at the start of `date`.
+pub fn local_midnight(date: NaiveDate) -> DateTime {
+    let dt = ist()
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap();
+    DateTime::from_chrono(dt)
+}
+
+/// Midnight IST at the end of `date`, for ranges that include it.
+pub fn end_of_day(date: NaiveDate) -> Result<DateTime> {
+    date.succ_opt()
+        .map(local_midnight)
+        .ok_or_else(|| Error::new(format!("{} is out of range", date), ErrorKind::InvalidData))
+}
+
+pub fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+pub fn next_month_start(date: NaiveDate) -> NaiveDate {
+    if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1).unwrap()
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1).unwrap()
+    }
+}
+
+/// End of the period a billing run made at `now` bills through: the start of the
+/// current month, so every run within a month bills the same periods.
+pub fn billing_cutoff(now: DateTime) -> DateTime {
+    local_midnight(month_start(local_date(now)))
+}
+
+pub fn days_in_month(date: NaiveDate) -> i64 {
+    (next_month_start(date) - month_start(date)).num_days()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BillingPeriod {
+    /// First day billed, inclusive.
+    pub start: NaiveDate,
+    /// Day after the last day billed.
+    pub end: NaiveDate,
+}
+
+impl BillingPeriod {
+    pub fn days(&self) -> i64 {
+        (self.end - self.start).num_days()
+    }
+
+    pub fn days_in_month(&self) -> i64 {
+        days_in_month(self.start)
+    }
+
+    /// `amount` for a full month, prorated over the days in this period.
+    pub fn prorate(&self, amount: Money) -> Money {
+        amount.mul_ratio(self.days(), self.days_in_month(), Rounding::HalfUp)
+    }
+
+    pub fn label(&self) -> String {
+        format!(
+            "{} - {}",
+            self.start.format("%Y-%m-%d"),
+            (self.end - Duration::days(1)).format("%Y-%m-%d")
+        )
+    }
+}
+
+/// Splits `[from, to)` into calendar-month periods, dropping empty ones.
+pub fn billing_periods(from: NaiveDate, to: NaiveDate) -> Vec<BillingPeriod> {
+    let mut periods = Vec::new();
+    let mut start = from;
+    while start < to {
+        let end = next_month_start(start).min(to);
+        periods.push(BillingPeriod { start, end });
+        start = end;
+    }
+    periods
+}
+
+pub fn calculate_usage(
+    from_date: DateTime,
+    to_date: DateTime,
+    pricing: OrganizationPricingTier,
+    additions: &[OrganizationPricingAdditions],
+    catalog: &PricingCatalog,
+    metered: &[UsageRecord],
+) -> Vec<OrganizationUsage> {
+    explain_usage(from_date, to_date, pricing, additions, catalog, metered)
+        .into_iter()
+        .map(|(usage, _)| usage)
+        .collect()
+}
+
+/// [`calculate_usage`] with a sentence per line saying how it was charged.
+pub fn explain_usage(
+    from_date: DateTime,
+    to_date: DateTime,
+    pricing: OrganizationPricingTier,
+    additions: &[OrganizationPricingAdditions],
+    catalog: &PricingCatalog,
+    metered: &[UsageRecord],
+) -> Vec<(OrganizationUsage, String)> {
+    let mut usage = Vec::new();
+    for period in billing_periods(local_date(from_date), local_date(to_date)) {
+        // Each period is priced by the plan in effect on its first billed day.
+        let info = catalog.resolve(pricing, local_midnight(period.start));
+        let price = Money::from_rupees(info.price as i64);
+        let mut plan = OrganizationUsage {
+            billing_period: period.label(),
+            plan: pricing.to_string(),
+            addition: None,
+            base_charge: period.prorate(price),
+            additional_usage_charges: Money::ZERO,
+        };
+        let mut explanation = format!(
+            "{} plan at {}/month for {} of {} days",
+            pricing,
+            price,
+            period.days(),
+            period.days_in_month()
+        );
+        // Metered use is only charged once its month is over, on the period closing it.
+        if period.end == next_month_start(period.start) {
+            let month = month_key(period.start);
+            if let Some((charge, overage)) = metered
+                .iter()
+                .find(|record| record.month == month)
+                .and_then(|record| overage(&info, record))
+            {
+                plan.additional_usage_charges = charge;
+                explanation = format!("{}; {}", explanation, overage);
+            }
+        }
+        usage.push((plan, explanation));
+        // Each set of addition counts is billed from the day it took effect until
+        // the next one did, so a change mid-period splits its lines.
+        for (index, held) in additions.iter().enumerate() {
+            let mut billed = period;
+            if let Some(effective_from) = held.effective_from {
+                billed.start = billed.start.max(local_date(effective_from));
+            }
+            if let Some(replaced_on) = additions
+                .get(index + 1)
+                .and_then(|next| next.effective_from)
+            {
+                billed.end = billed.end.min(local_date(replaced_on));
+            }
+            if billed.days() <= 0 {
+                continue;
+            }
+            for (kind, quantity) in info.usable_additions(*held).quantities() {
+                let unit_price = info.addition_pricing.unit_price(kind);
+                if quantity == 0 || unit_price == 0 {
+                    continue;
+                }
+                let charge = Money::from_rupees((unit_price * quantity) as i64).mul_ratio(
+                    billed.days(),
+                    period.days_in_month(),
+                    Rounding::HalfUp,
+                );
+                usage.push((
+                    OrganizationUsage {
+                        billing_period: billed.label(),
+                        plan: pricing.to_string(),
+                        addition: Some(OrganizationAdditionUsage {
+                            kind,
+                            quantity,
+                            unit_price,
+                            days: billed.days() as u32,
+                        }),
+                        base_charge: charge,
+                        additional_usage_charges: Money::ZERO,
+                    },
+                    format!(
+                        "{} x {} at {}/month each for {} of {} days",
+                        quantity,
+                        kind,
+                        Money::from_rupees(unit_price as i64),
+                        billed.days(),
+                        period.days_in_month()
+                    ),
+                ));
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn billing_periods_split_at_month_boundaries() {
+        let periods = billing_periods(date(2023, 12, 15), date(2024, 2, 10));
+        assert_eq!(
+            periods,
+            vec![
+                BillingPeriod {
+                    start: date(2023, 12, 15),
+                    end: date(2024, 1, 1),
+                },
+                BillingPeriod {
+                    start: date(2024, 1, 1),
+                    end: date(2024, 2, 1),
+                },
+                BillingPeriod {
+                    start: date(2024, 2, 1),
+                    end: date(2024, 2, 10),
+                },
+            ]
+        );
+        assert_eq!(periods[0].days(), 17);
+        assert_eq!(periods[0].label(), "2023-12-15 - 2023-12-31");
+        assert!(billing_periods(date(2024, 3, 1), date(2024, 3, 1)).is_empty());
+    }
+
+    #[test]
+    fn february_has_twenty_nine_days_in_a_leap_year() {
+        assert_eq!(days_in_month(date(2024, 2, 10)), 29);
+        assert_eq!(days_in_month(date(2023, 2, 10)), 28);
+        assert_eq!(days_in_month(date(2100, 2, 10)), 28);
+        assert_eq!(days_in_month(date(2000, 2, 10)), 29);
+        let periods = billing_periods(date(2024, 2, 1), date(2024, 3, 1));
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].days(), 29);
+    }
+
+    #[test]
+    fn prorate_rounds_half_paise_up() {
+        let monthly = Money::from_rupees(999);
+        let full = BillingPeriod {
+            start: date(2024, 2, 1),
+            end: date(2024, 3, 1),
+        };
+        assert_eq!(full.prorate(monthly), monthly);
+        let leap_day = BillingPeriod {
+            start: date(2024, 2, 29),
+            end: date(2024, 3, 1),
+        };
+        // 99900 / 29 = 3444.83 paise.
+        assert_eq!(leap_day.prorate(monthly), Money::from_paise(3445));
+        let last_day = BillingPeriod {
+            start: date(2023, 2, 28),
+            end: date(2023, 3, 1),
+        };
+        // 99900 / 28 = 3567.86 paise.
+        assert_eq!(last_day.prorate(monthly), Money::from_paise(3568));
+        let half = BillingPeriod {
+            start: date(2024, 4, 1),
+            end: date(2024, 4, 16),
+        };
+        // 101 * 15 / 30 = 50.5 paise.
+        assert_eq!(half.prorate(Money::from_paise(101)), Money::from_paise(51));
+    }
+
+    #[test]
+    fn local_dates_are_reckoned_in_ist() {
+        let midnight = local_midnight(date(2026, 4, 1));
+        assert_eq!(local_date(midnight), date(2026, 4, 1));
+        // 18:30 UTC on 31 March is already 1 April in India.
+        assert_eq!(
+            midnight.to_chrono().naive_utc(),
+            date(2026, 3, 31).and_hms_opt(18, 30, 0).unwrap()
+        );
+        assert_eq!(next_month_start(date(2026, 12, 31)), date(2027, 1, 1));
+    }
+
+    fn users(count: usize, effective_from: Option<NaiveDate>) -> OrganizationPricingAdditions {
+        OrganizationPricingAdditions {
+            users: count,
+            effective_from: effective_from.map(local_midnight),
+            ..Default::default()
+        }
+    }
+
+    /// Billing period, quantity, days and charge of each addition line.
+    fn addition_lines(
+        additions: &[OrganizationPricingAdditions],
+    ) -> Vec<(String, usize, u32, Money)> {
+        explain_usage(
+            local_midnight(date(2026, 4, 1)),
+            local_midnight(date(2026, 5, 1)),
+            OrganizationPricingTier::T5,
+            additions,
+            &PricingCatalog::default(),
+            &[],
+        )
+        .into_iter()
+        .filter_map(|(usage, _)| {
+            usage.addition.map(|addition| {
+                (
+                    usage.billing_period.clone(),
+                    addition.quantity,
+                    addition.days,
+                    usage.base_charge,
+                )
+            })
+        })
+        .collect()
+    }
+
+    #[test]
+    fn additions_bought_mid_period_bill_from_the_day_they_took_effect() {
+        // 3 users at 59.00 for 16 of 30 days.
+        assert_eq!(
+            addition_lines(&[users(3, Some(date(2026, 4, 15)))]),
+            vec![(
+                String::from("2026-04-15 - 2026-04-30"),
+                3,
+                16,
+                Money::from_paise(9440)
+            )]
+        );
+        assert_eq!(
+            addition_lines(&[users(3, None)]),
+            vec![(
+                String::from("2026-04-01 - 2026-04-30"),
+                3,
+                30,
+                Money::from_rupees(177)
+            )]
+        );
+    }
+
+    #[test]
+    fn a_change_mid_period_bills_the_earlier_counts_up_to_it() {
+        let additions = [users(2, None), users(3, Some(date(2026, 4, 15)))];
+        assert_eq!(
+            addition_lines(&additions),
+            vec![
+                // 2 users at 59.00 for 14 of 30 days is 55.066 rupees.
+                (
+                    String::from("2026-04-01 - 2026-04-14"),
+                    2,
+                    14,
+                    Money::from_paise(5507)
+                ),
+                (
+                    String::from("2026-04-15 - 2026-04-30"),
+                    3,
+                    16,
+                    Money::from_paise(9440)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn end_of_day_is_the_next_midnight() {
+        assert_eq!(
+            end_of_day(date(2024, 2, 28)).unwrap(),
+            local_midnight(date(2024, 2, 29))
+        );
+        assert!(end_of_day(NaiveDate::MAX).is_err());
+    }
+
+    #[test]
+    fn runs_within_a_month_bill_through_its_start() {
+        let cutoff = local_midnight(date(2026, 10, 1));
+        assert_eq!(billing_cutoff(cutoff), cutoff);
+        assert_eq!(billing_cutoff(local_midnight(date(2026, 10, 15))), cutoff);
+        // 23:59 IST on 31 October is still October.
+        let last_minute =
+            DateTime::from_millis(local_midnight(date(2026, 11, 1)).timestamp_millis() - 60_000);
+        assert_eq!(billing_cutoff(last_minute), cutoff);
+    }
+}
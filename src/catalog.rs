@@ -0,0 +1,86 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::FindOptions,
+    Database,
+};
+use serde::Deserialize;
+
+use crate::billing::{local_date, local_midnight};
+use crate::date::Date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{OrganizationPricing, OrganizationPricingTier, PricingVersion};
+use crate::sequence::next_value;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PricingVersionRequest {
+    /// First day the plan applies, today or later.
+    pub effective_from: Date,
+    /// Full plan definition; `plan.tier` names the tier it replaces.
+    pub plan: OrganizationPricing,
+}
+
+/// Every plan version in the catalog, loaded once per billing run or request so
+/// that a price change takes effect without a redeploy.
+#[derive(Debug, Clone, Default)]
+pub struct PricingCatalog {
+    /// Ordered by `effective_from`, then `version`.
+    versions: Vec<PricingVersion>,
+}
+
+impl PricingCatalog {
+    pub async fn load(db: &Database) -> Result<PricingCatalog> {
+        let versions = PricingVersion::collection(db)
+            .find(
+                doc! {},
+                FindOptions::builder()
+                    .sort(doc! {"effectiveFrom": 1, "version": 1})
+                    .build(),
+            )
+            .await?
+            .try_collect::<Vec<PricingVersion>>()
+            .await?;
+        Ok(PricingCatalog { versions })
+    }
+
+    pub fn versions(&self) -> &[PricingVersion] {
+        &self.versions
+    }
+
+    /// Plan of `tier` in effect at `at`; the built-in plan before the tier's first
+    /// catalog version.
+    pub fn resolve(&self, tier: OrganizationPricingTier, at: DateTime) -> OrganizationPricing {
+        self.versions
+            .iter()
+            .rev()
+            .find(|version| version.tier == tier && version.effective_from <= at)
+            .map(|version| version.plan.clone())
+            .unwrap_or_else(|| tier.info())
+    }
+}
+
+/// Adds a new version of a tier's plan. Versions only take effect from today on,
+/// so invoices already computed keep the prices they were issued with.
+pub async fn publish(db: &Database, request: PricingVersionRequest) -> Result<PricingVersion> {
+    let today = local_date(DateTime::now());
+    if request.effective_from.value() < today {
+        return Err(Error::new(
+            "Prices cannot change retroactively; effectiveFrom must be today or later",
+            ErrorKind::InvalidData,
+        ));
+    }
+    let tier = request.plan.tier;
+    let version = PricingVersion {
+        id: ObjectId::new(),
+        tier,
+        version: next_value(db, &format!("PRICING/{}", tier)).await?,
+        effective_from: local_midnight(request.effective_from.value()),
+        plan: request.plan,
+        created_at: DateTime::now(),
+    };
+    PricingVersion::collection(db)
+        .insert_one(&version, None)
+        .await?;
+    Ok(version)
+}
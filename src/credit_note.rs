@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    Client, ClientSession, Database,
+};
+use serde::Deserialize;
+
+use crate::dunning::reactivate_if_settled;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{CreditNote, CreditNoteLine, Invoice, PaidStatus};
+use crate::money::{Money, Rounding};
+use crate::payment::refresh_balance_with_session;
+use crate::sequence::Numbering;
+use crate::tax::{compute_tax, invoice_supply_type};
+use crate::transaction;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNoteLineRequest {
+    /// Position of the line in the invoice's `organization_usage`.
+    pub line: usize,
+    /// Taxable amount to reverse, in paise; the rest of the line when omitted.
+    pub amount: Option<Money>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNoteRequest {
+    pub reason: String,
+    /// Lines to reverse; every line still uncredited when omitted.
+    pub lines: Option<Vec<CreditNoteLineRequest>>,
+}
+
+/// Taxable amount already reversed on each invoice line.
+async fn credited_lines(
+    db: &Database,
+    session: &mut ClientSession,
+    invoice: &Invoice,
+) -> Result<HashMap<usize, Money>> {
+    let mut credited = HashMap::new();
+    let credit_notes = CreditNote::collection(db)
+        .find_with_session(doc! {"invoice": invoice.id}, None, session)
+        .await?
+        .stream(session)
+        .try_collect::<Vec<CreditNote>>()
+        .await?;
+    for line in credit_notes
+        .iter()
+        .flat_map(|credit_note| &credit_note.lines)
+    {
+        *credited.entry(line.line).or_insert(Money::ZERO) += line.amount;
+    }
+    Ok(credited)
+}
+
+/// Issues a credit note reversing all or part of a finalized invoice. The check
+/// against earlier credits, the number and the note are written in one
+/// transaction, so concurrent requests cannot over-credit a line or leave a gap
+/// in the series.
+pub async fn issue_credit_note(
+    client: &Client,
+    db: &Database,
+    numbering: &Numbering,
+    invoice: &Invoice,
+    request: CreditNoteRequest,
+) -> Result<CreditNote> {
+    let invoice_no = match (&invoice.invoice_no, invoice.draft) {
+        (Some(invoice_no), false) => invoice_no.clone(),
+        _ => {
+            return Err(Error::new(
+                "Draft invoices are corrected by editing, not by a credit note",
+                ErrorKind::LogicalError,
+            ))
+        }
+    };
+    if request.reason.trim().is_empty() {
+        return Err(Error::new(
+            "A credit note needs a reason",
+            ErrorKind::InvalidData,
+        ));
+    }
+    let mut attempt = 1;
+    loop {
+        let mut session = transaction::start(client).await?;
+        let result =
+            issue_with_session(db, &mut session, numbering, invoice, &invoice_no, &request).await;
+        match transaction::finish(&mut session, result).await {
+            Err(err) if transaction::should_retry(&err, attempt) => attempt += 1,
+            Err(err) => return Err(err),
+            Ok((credit_note, invoice)) => {
+                if invoice.paid_status == PaidStatus::Paid {
+                    reactivate_if_settled(db, &invoice.organization).await?;
+                }
+                return Ok(credit_note);
+            }
+        }
+    }
+}
+
+async fn issue_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    numbering: &Numbering,
+    invoice: &Invoice,
+    invoice_no: &str,
+    request: &CreditNoteRequest,
+) -> Result<(CreditNote, Invoice)> {
+    let mut credited = credited_lines(db, session, invoice).await?;
+    let requested = match &request.lines {
+        Some(lines) => lines.clone(),
+        None => (0..invoice.organization_usage.len())
+            .map(|line| CreditNoteLineRequest { line, amount: None })
+            .collect(),
+    };
+
+    let mut lines = Vec::new();
+    for CreditNoteLineRequest { line, amount } in requested {
+        let usage = invoice.organization_usage.get(line).ok_or_else(|| {
+            Error::new(
+                format!("Invoice has no line {}", line),
+                ErrorKind::InvalidData,
+            )
+        })?;
+        let credited = credited.entry(line).or_insert(Money::ZERO);
+        let available = usage.amount() - *credited;
+        let amount = amount.unwrap_or(available);
+        if amount.is_negative() || amount > available {
+            return Err(Error::new(
+                format!(
+                    "Line {} can be credited by at most {}, not {}",
+                    line, available, amount
+                ),
+                ErrorKind::LogicalError,
+            ));
+        }
+        if amount.is_zero() {
+            continue;
+        }
+        *credited += amount;
+        lines.push(CreditNoteLine {
+            line,
+            description: usage.description(),
+            billing_period: usage.billing_period.clone(),
+            amount,
+        });
+    }
+    if lines.is_empty() {
+        return Err(Error::new(
+            "Nothing left to credit on this invoice",
+            ErrorKind::LogicalError,
+        ));
+    }
+
+    let service_value: Money = lines.iter().map(|line| line.amount).sum();
+    let tax = compute_tax(
+        service_value,
+        invoice.tax_ratio,
+        invoice_supply_type(invoice),
+    );
+    let tax_value = tax.total();
+    let total_value = service_value + tax_value;
+    let now = DateTime::now();
+    let credit_note = CreditNote {
+        id: ObjectId::new(),
+        credit_note_no: numbering.next_with_session(db, session, now).await?,
+        date: now,
+        invoice: invoice.id,
+        invoice_no: invoice_no.to_string(),
+        organization: invoice.organization.clone(),
+        reason: request.reason.clone(),
+        lines,
+        service_value,
+        place_of_supply: invoice.place_of_supply.clone(),
+        tax_ratio: tax.tax_ratio,
+        cgst_value: tax.cgst_value,
+        sgst_value: tax.sgst_value,
+        igst_value: tax.igst_value,
+        tax_value,
+        total_value,
+        rounded_value: total_value.round_to_rupee(Rounding::HalfUp),
+        created_at: now,
+    };
+    CreditNote::collection(db)
+        .insert_one_with_session(&credit_note, None, session)
+        .await?;
+    // Also writes the invoice, so a concurrent credit note on it conflicts and retries.
+    let invoice = refresh_balance_with_session(db, session, invoice).await?;
+    Ok((credit_note, invoice))
+}
@@ -1,38 +1,32 @@
+use std::fmt;
 use std::result;
 use std::str::FromStr;
 
-use chrono::{
-    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
-};
-use db::bson::Bson;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use mongodb::bson::Bson;
 use serde::{de, Deserialize, Serialize, Serializer};
 
-use errors::{Error, ErrorKind};
+use crate::error::{Error, ErrorKind};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Date(NaiveDate);
 
 impl Date {
     pub fn value(&self) -> NaiveDate {
-        self.0.clone()
+        self.0
     }
 
     pub fn today() -> Self {
-        let today = Local::today();
-        Self(NaiveDate::from_ymd(
-            today.year(),
-            today.month(),
-            today.day(),
-        ))
+        Self(Local::now().date_naive())
     }
 
     pub fn to_dt(&self) -> DateTime<Utc> {
-        let datetime = NaiveDateTime::new(self.value(), NaiveTime::from_hms(0, 0, 0));
+        let datetime = NaiveDateTime::new(self.value(), NaiveTime::from_hms_opt(0, 0, 0).unwrap());
         Utc.from_local_datetime(&datetime).unwrap()
     }
 
     pub fn add_days(&mut self, days: i64) {
-        self.0 = self.0 + Duration::days(days);
+        self.0 += Duration::days(days);
     }
 
     pub fn to_fmt_string(&self, fmt: &'static str) -> String {
@@ -49,9 +43,9 @@ impl FromStr for Date {
     }
 }
 
-impl ToString for Date {
-    fn to_string(&self) -> String {
-        self.value().format("%Y-%m-%d").to_string()
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value().format("%Y-%m-%d"))
     }
 }
 
@@ -61,22 +55,18 @@ impl From<Date> for Bson {
     }
 }
 
-impl Into<DateTime<Utc>> for Date {
-    fn into(self) -> DateTime<Utc> {
-        let date = self.value();
-        let time = NaiveTime::from_hms(0, 0, 0);
+impl From<Date> for DateTime<Utc> {
+    fn from(date: Date) -> Self {
+        let date = date.value();
+        let time = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
         let datetime = NaiveDateTime::new(date, time);
         Utc.from_local_datetime(&datetime).unwrap()
     }
 }
 
-impl From<db::bson::DateTime> for Date {
-    fn from(item: db::bson::DateTime) -> Self {
-        Self(NaiveDate::from_ymd(
-            item.to_chrono().year(),
-            item.to_chrono().month(),
-            item.to_chrono().day(),
-        ))
+impl From<mongodb::bson::DateTime> for Date {
+    fn from(item: mongodb::bson::DateTime) -> Self {
+        Self(item.to_chrono().date_naive())
     }
 }
 
@@ -0,0 +1,348 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::NaiveDate;
+use futures::{future::BoxFuture, TryStreamExt};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::FindOneOptions,
+    Database,
+};
+use serde::Serialize;
+
+use crate::billing::local_date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{
+    DunningEvent, DunningEventKind, FailedOrganization, Invoice, Organization, OrganizationStatus,
+    PaidStatus,
+};
+
+/// Days past due at which payment reminders go out.
+#[derive(Debug, Clone)]
+pub struct DunningConfig {
+    pub reminder_offsets: Vec<i64>,
+}
+
+impl DunningConfig {
+    /// Reads `DUNNING_REMINDER_DAYS`, a comma separated list such as `1,7,14`.
+    pub fn from_env() -> DunningConfig {
+        let mut reminder_offsets = std::env::var("DUNNING_REMINDER_DAYS")
+            .unwrap_or_else(|_| String::from("1,7,14"))
+            .split(',')
+            .map(|offset| {
+                offset
+                    .trim()
+                    .parse::<i64>()
+                    .expect("DUNNING_REMINDER_DAYS must be a list of day counts")
+            })
+            .collect::<Vec<_>>();
+        reminder_offsets.sort_unstable();
+        reminder_offsets.dedup();
+        DunningConfig { reminder_offsets }
+    }
+}
+
+/// Delivers payment reminders to organizations.
+pub trait Notifier: Send + Sync {
+    fn send_reminder<'a>(
+        &'a self,
+        organization: &'a Organization,
+        invoice: &'a Invoice,
+        days_overdue: i64,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Chooses the notifier from `DUNNING_NOTIFIER`. Without one, every reminder
+/// fails and none is recorded as sent.
+pub fn notifier_from_env() -> Arc<dyn Notifier> {
+    match std::env::var("DUNNING_NOTIFIER").as_deref() {
+        Err(_) | Ok("") => Arc::new(UnconfiguredNotifier),
+        Ok("log") => Arc::new(LogNotifier),
+        Ok(other) => panic!("DUNNING_NOTIFIER {} is not a known notifier", other),
+    }
+}
+
+/// Refuses every reminder; used until a delivery channel is configured.
+#[derive(Debug, Default, Clone)]
+pub struct UnconfiguredNotifier;
+
+impl Notifier for UnconfiguredNotifier {
+    fn send_reminder<'a>(
+        &'a self,
+        _organization: &'a Organization,
+        _invoice: &'a Invoice,
+        _days_overdue: i64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            Err(Error::with_code(
+                "No reminder delivery channel is configured",
+                "NOTIFIER_NOT_CONFIGURED",
+                ErrorKind::LogicalError,
+            ))
+        })
+    }
+}
+
+/// Writes reminders to the service log instead of delivering them, yet records
+/// them as sent, so it is only for development (`DUNNING_NOTIFIER=log`).
+#[derive(Debug, Default, Clone)]
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send_reminder<'a>(
+        &'a self,
+        organization: &'a Organization,
+        invoice: &'a Invoice,
+        days_overdue: i64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            println!(
+                "Reminder to {}: invoice {} has {} outstanding, {} days overdue",
+                organization.name,
+                invoice.invoice_no.as_deref().unwrap_or("-"),
+                invoice.outstanding(),
+                days_overdue
+            );
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DunningReport {
+    pub reminders: usize,
+    pub suspended: Vec<String>,
+    pub reactivated: Vec<String>,
+    /// Reminders that could not be delivered; they are sent again on the next run.
+    pub failed: Vec<FailedOrganization>,
+}
+
+fn days_overdue(invoice: &Invoice, today: NaiveDate) -> i64 {
+    invoice
+        .due_date()
+        .map(|due_date| (today - local_date(due_date)).num_days())
+        .unwrap_or(0)
+}
+
+async fn unpaid_invoices(db: &Database, organization: Option<&str>) -> Result<Vec<Invoice>> {
+    let mut filter = doc! {
+        "draft": false,
+        "paidStatus": {"$in": [PaidStatus::Unpaid, PaidStatus::PartiallyPaid]},
+    };
+    if let Some(organization) = organization {
+        filter.insert("organization", organization);
+    }
+    Ok(Invoice::collection(db)
+        .find(filter, None)
+        .await?
+        .try_collect::<Vec<Invoice>>()
+        .await?)
+}
+
+async fn record(db: &Database, event: DunningEvent) -> Result<()> {
+    DunningEvent::collection(db)
+        .insert_one(&event, None)
+        .await?;
+    Ok(())
+}
+
+fn event(organization: &Organization, kind: DunningEventKind, reason: String) -> DunningEvent {
+    DunningEvent {
+        id: ObjectId::new(),
+        organization: organization.name.clone(),
+        kind,
+        invoice: None,
+        offset_days: None,
+        from_status: None,
+        to_status: None,
+        reason,
+        created_at: DateTime::now(),
+    }
+}
+
+async fn transition(
+    db: &Database,
+    organization: &Organization,
+    from: OrganizationStatus,
+    to: OrganizationStatus,
+    kind: DunningEventKind,
+    reason: String,
+) -> Result<bool> {
+    let result = Organization::collection(db)
+        .update_one(
+            doc! {"_id": organization.id, "status": from},
+            doc! {"$set": {"status": to, "updatedAt": DateTime::now()}},
+            None,
+        )
+        .await?;
+    if result.modified_count == 0 {
+        return Ok(false);
+    }
+    let mut event = event(organization, kind, reason);
+    event.from_status = Some(from);
+    event.to_status = Some(to);
+    record(db, event).await?;
+    Ok(true)
+}
+
+/// Sends due reminders, suspends organizations whose invoices are overdue beyond
+/// their grace period and reactivates those that have since settled.
+pub async fn run(
+    db: &Database,
+    config: &DunningConfig,
+    notifier: &dyn Notifier,
+) -> Result<DunningReport> {
+    let today = local_date(DateTime::now());
+    let mut report = DunningReport::default();
+    let mut by_organization: BTreeMap<String, Vec<Invoice>> = BTreeMap::new();
+    for invoice in unpaid_invoices(db, None).await? {
+        by_organization
+            .entry(invoice.organization.clone())
+            .or_default()
+            .push(invoice);
+    }
+    for (name, invoices) in by_organization {
+        let organization = match Organization::collection(db)
+            .find_one(doc! {"name": &name}, None)
+            .await?
+        {
+            Some(organization) => organization,
+            None => continue,
+        };
+        for invoice in &invoices {
+            let overdue = days_overdue(invoice, today);
+            // After downtime several offsets may have passed; only the latest is sent.
+            let offset = match config
+                .reminder_offsets
+                .iter()
+                .rev()
+                .find(|offset| overdue >= **offset)
+            {
+                Some(offset) => *offset,
+                None => continue,
+            };
+            let sent = DunningEvent::collection(db)
+                .count_documents(
+                    doc! {
+                        "kind": DunningEventKind::Reminder,
+                        "invoice": invoice.id,
+                        "offsetDays": {"$gte": offset},
+                    },
+                    None,
+                )
+                .await?;
+            if sent > 0 {
+                continue;
+            }
+            if let Err(err) = notifier
+                .send_reminder(&organization, invoice, overdue)
+                .await
+            {
+                report.failed.push(FailedOrganization {
+                    organization: organization.name.clone(),
+                    code: err.code().to_string(),
+                    message: format!(
+                        "Reminder for invoice {} could not be sent: {}",
+                        invoice.invoice_no.as_deref().unwrap_or("-"),
+                        err
+                    ),
+                });
+                continue;
+            }
+            let mut reminder = event(
+                &organization,
+                DunningEventKind::Reminder,
+                format!("Invoice is {} days overdue", overdue),
+            );
+            reminder.invoice = Some(invoice.id);
+            reminder.offset_days = Some(offset);
+            record(db, reminder).await?;
+            report.reminders += 1;
+        }
+
+        let grace_period = organization.grace_period as i64;
+        let lapsed = invoices
+            .iter()
+            .map(|invoice| (invoice, days_overdue(invoice, today)))
+            .filter(|(_, overdue)| *overdue > grace_period)
+            .max_by_key(|(_, overdue)| *overdue);
+        if let (OrganizationStatus::Active, Some((invoice, overdue))) =
+            (organization.status, lapsed)
+        {
+            let reason = format!(
+                "Invoice {} is {} days overdue, beyond the grace period of {} days",
+                invoice.invoice_no.as_deref().unwrap_or("-"),
+                overdue,
+                grace_period
+            );
+            if transition(
+                db,
+                &organization,
+                OrganizationStatus::Active,
+                OrganizationStatus::Suspended,
+                DunningEventKind::Suspended,
+                reason,
+            )
+            .await?
+            {
+                report.suspended.push(organization.name.clone());
+            }
+        }
+    }
+
+    let suspended = Organization::collection(db)
+        .find(doc! {"status": OrganizationStatus::Suspended}, None)
+        .await?
+        .try_collect::<Vec<Organization>>()
+        .await?;
+    for organization in suspended {
+        if reactivate_if_settled(db, &organization.name).await? {
+            report.reactivated.push(organization.name);
+        }
+    }
+    Ok(report)
+}
+
+/// Reactivates an organization that dunning suspended once none of its invoices
+/// are overdue beyond the grace period. Manual suspensions are left alone.
+pub async fn reactivate_if_settled(db: &Database, organization: &str) -> Result<bool> {
+    let organization = match Organization::collection(db)
+        .find_one(doc! {"name": organization}, None)
+        .await?
+    {
+        Some(organization) if organization.status == OrganizationStatus::Suspended => organization,
+        _ => return Ok(false),
+    };
+    let last_change = DunningEvent::collection(db)
+        .find_one(
+            doc! {
+                "organization": &organization.name,
+                "kind": {"$in": [DunningEventKind::Suspended, DunningEventKind::Reactivated]},
+            },
+            FindOneOptions::builder()
+                .sort(doc! {"createdAt": -1})
+                .build(),
+        )
+        .await?;
+    if !matches!(last_change, Some(event) if event.kind == DunningEventKind::Suspended) {
+        return Ok(false);
+    }
+    let today = local_date(DateTime::now());
+    let grace_period = organization.grace_period as i64;
+    let lapsed = unpaid_invoices(db, Some(&organization.name))
+        .await?
+        .iter()
+        .any(|invoice| days_overdue(invoice, today) > grace_period);
+    if lapsed {
+        return Ok(false);
+    }
+    transition(
+        db,
+        &organization,
+        OrganizationStatus::Suspended,
+        OrganizationStatus::Active,
+        DunningEventKind::Reactivated,
+        String::from("Overdue invoices settled"),
+    )
+    .await
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use futures::future::BoxFuture;
+use mongodb::bson::DateTime;
+use serde::Serialize;
+
+use crate::billing::local_date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{EInvoiceRegistration, Invoice, Organization};
+use crate::supplier::Supplier;
+use crate::tax::{compute_tax, invoice_supply_type, state_code, SAC_CODE};
+
+/// An invoice in the government INV-01 e-invoice schema, version 1.1.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EInvoice {
+    pub version: &'static str,
+    pub tran_dtls: TranDtls,
+    pub doc_dtls: DocDtls,
+    pub seller_dtls: PartyDtls,
+    pub buyer_dtls: PartyDtls,
+    pub item_list: Vec<ItemDtls>,
+    pub val_dtls: ValDtls,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TranDtls {
+    pub tax_sch: &'static str,
+    pub sup_typ: &'static str,
+    pub reg_rev: &'static str,
+    pub igst_on_intra: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DocDtls {
+    pub typ: &'static str,
+    pub no: String,
+    pub dt: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PartyDtls {
+    pub gstin: String,
+    pub lgl_nm: String,
+    /// Place of supply; only present for the buyer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos: Option<String>,
+    pub addr1: String,
+    pub loc: String,
+    pub pin: u32,
+    pub stcd: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ItemDtls {
+    pub sl_no: String,
+    pub prd_desc: String,
+    pub is_servc: &'static str,
+    pub hsn_cd: &'static str,
+    pub qty: f64,
+    pub unit: &'static str,
+    pub unit_price: f64,
+    pub tot_amt: f64,
+    pub ass_amt: f64,
+    pub gst_rt: f32,
+    pub igst_amt: f64,
+    pub cgst_amt: f64,
+    pub sgst_amt: f64,
+    pub tot_item_val: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ValDtls {
+    pub ass_val: f64,
+    pub cgst_val: f64,
+    pub sgst_val: f64,
+    pub igst_val: f64,
+    pub rnd_off_amt: f64,
+    pub tot_inv_val: f64,
+}
+
+fn is_gstin(value: &str) -> bool {
+    value.len() == 15
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+        && value.get(0..2).and_then(state_code).is_some()
+}
+
+fn parse_pin(value: Option<&str>) -> Option<u32> {
+    value
+        .map(str::trim)
+        .filter(|pin| pin.len() == 6)
+        .and_then(|pin| pin.parse().ok())
+}
+
+/// Builds the INV-01 payload, reporting every mandatory field that is missing.
+pub fn build(
+    invoice: &Invoice,
+    organization: &Organization,
+    supplier: &Supplier,
+) -> Result<EInvoice> {
+    let mut missing = Vec::new();
+    let buyer_gst_no = organization.gst_no.clone().unwrap_or_default();
+    if !is_gstin(&buyer_gst_no) {
+        missing.push("buyer GSTIN");
+    }
+    if !is_gstin(&supplier.gst_no) {
+        missing.push("supplier GSTIN");
+    }
+    if invoice.draft || invoice.invoice_no.is_none() {
+        missing.push("invoice number (the invoice is still a draft)");
+    }
+    // The document date is the day the invoice was issued, not the end of the
+    // period it bills; drafts are only finalized at the following run.
+    if invoice.issued_at.is_none() {
+        missing.push("issue date");
+    }
+    if invoice.organization_usage.is_empty() {
+        missing.push("invoice lines");
+    }
+    let address = &organization.billing_address;
+    let buyer_pin = parse_pin(address.pin_code.as_deref());
+    if buyer_pin.is_none() {
+        missing.push("buyer pin code");
+    }
+    let buyer_loc = address.city.clone().unwrap_or_default();
+    if buyer_loc.trim().len() < 3 {
+        missing.push("buyer location");
+    }
+    let buyer_state = address
+        .state
+        .as_deref()
+        .and_then(state_code)
+        .or_else(|| buyer_gst_no.get(0..2).and_then(state_code));
+    if buyer_state.is_none() {
+        missing.push("buyer state");
+    }
+    let supplier_pin = parse_pin(supplier.pin_code.as_deref());
+    if supplier_pin.is_none() {
+        missing.push("supplier pin code");
+    }
+    let supplier_loc = supplier.city.clone().unwrap_or_default();
+    if supplier_loc.trim().len() < 3 {
+        missing.push("supplier location");
+    }
+    let place_of_supply = invoice
+        .place_of_supply
+        .clone()
+        .or_else(|| buyer_state.map(String::from));
+    if !missing.is_empty() {
+        return Err(Error::with_code(
+            format!("E-invoice is missing: {}", missing.join(", ")),
+            "EINVOICE_INVALID",
+            ErrorKind::InvalidData,
+        ));
+    }
+
+    let supply_type = invoice_supply_type(invoice);
+    let item_list = invoice
+        .organization_usage
+        .iter()
+        .enumerate()
+        .map(|(index, usage)| {
+            let amount = usage.amount();
+            let tax = compute_tax(amount, invoice.tax_ratio, supply_type);
+            ItemDtls {
+                sl_no: (index + 1).to_string(),
+                prd_desc: format!("{} ({})", usage.description(), usage.billing_period),
+                is_servc: "Y",
+                hsn_cd: SAC_CODE,
+                qty: 1.0,
+                unit: "OTH",
+                unit_price: amount.to_f64(),
+                tot_amt: amount.to_f64(),
+                ass_amt: amount.to_f64(),
+                gst_rt: invoice.tax_ratio,
+                igst_amt: tax.igst_value.to_f64(),
+                cgst_amt: tax.cgst_value.to_f64(),
+                sgst_amt: tax.sgst_value.to_f64(),
+                tot_item_val: (amount + tax.total()).to_f64(),
+            }
+        })
+        .collect();
+    let supplier_addr = supplier
+        .address
+        .lines()
+        .next()
+        .unwrap_or(&supplier.name)
+        .to_string();
+
+    Ok(EInvoice {
+        version: "1.1",
+        tran_dtls: TranDtls {
+            tax_sch: "GST",
+            sup_typ: "B2B",
+            reg_rev: "N",
+            igst_on_intra: "N",
+        },
+        doc_dtls: DocDtls {
+            typ: "INV",
+            no: invoice.invoice_no.clone().unwrap_or_default(),
+            dt: invoice
+                .issued_at
+                .map(|issued_at| local_date(issued_at).format("%d/%m/%Y").to_string())
+                .unwrap_or_default(),
+        },
+        seller_dtls: PartyDtls {
+            gstin: supplier.gst_no.clone(),
+            lgl_nm: supplier.name.clone(),
+            pos: None,
+            addr1: supplier_addr,
+            loc: supplier_loc,
+            pin: supplier_pin.unwrap_or_default(),
+            stcd: supplier.state.clone(),
+        },
+        buyer_dtls: PartyDtls {
+            gstin: buyer_gst_no,
+            lgl_nm: organization.full_name.clone(),
+            pos: place_of_supply,
+            addr1: address.street.clone().unwrap_or_else(|| buyer_loc.clone()),
+            loc: buyer_loc,
+            pin: buyer_pin.unwrap_or_default(),
+            stcd: buyer_state.unwrap_or_default().to_string(),
+        },
+        item_list,
+        val_dtls: ValDtls {
+            ass_val: invoice.service_value.to_f64(),
+            cgst_val: invoice.cgst_value.to_f64(),
+            sgst_val: invoice.sgst_value.to_f64(),
+            igst_val: invoice.igst_value.to_f64(),
+            rnd_off_amt: (invoice.rounded_value - invoice.total_value).to_f64(),
+            tot_inv_val: invoice.rounded_value.to_f64(),
+        },
+    })
+}
+
+/// Submits e-invoices to an invoice registration portal.
+pub trait IrpClient: Send + Sync {
+    fn generate_irn<'a>(
+        &'a self,
+        payload: &'a EInvoice,
+    ) -> BoxFuture<'a, Result<EInvoiceRegistration>>;
+}
+
+/// Picks the portal client named by `IRP_CLIENT`. With none set, registration
+/// is refused rather than recording IRNs the portal never issued.
+pub fn client_from_env() -> Arc<dyn IrpClient> {
+    match std::env::var("IRP_CLIENT").as_deref() {
+        Err(_) | Ok("") => Arc::new(UnconfiguredIrpClient),
+        Ok("stub") => Arc::new(StubIrpClient),
+        Ok(other) => panic!("IRP_CLIENT {} is not a known portal client", other),
+    }
+}
+
+/// Refuses every registration; used until a portal client is configured.
+#[derive(Debug, Default, Clone)]
+pub struct UnconfiguredIrpClient;
+
+impl IrpClient for UnconfiguredIrpClient {
+    fn generate_irn<'a>(
+        &'a self,
+        _payload: &'a EInvoice,
+    ) -> BoxFuture<'a, Result<EInvoiceRegistration>> {
+        Box::pin(async move {
+            Err(Error::with_code(
+                "No invoice registration portal is configured",
+                "IRP_NOT_CONFIGURED",
+                ErrorKind::LogicalError,
+            ))
+        })
+    }
+}
+
+const STUB_QR_PREFIX: &str = "STUB.";
+
+/// Whether `registration` was made up by [`StubIrpClient`] rather than issued by
+/// a portal, so the invoice may still be registered for real.
+pub fn is_stub(registration: &EInvoiceRegistration) -> bool {
+    registration.signed_qr_code.starts_with(STUB_QR_PREFIX)
+}
+
+/// Registers invoices locally without contacting a portal. Its IRNs are made up,
+/// so it is only for development (`IRP_CLIENT=stub`) and tests.
+#[derive(Debug, Default, Clone)]
+pub struct StubIrpClient;
+
+impl IrpClient for StubIrpClient {
+    fn generate_irn<'a>(
+        &'a self,
+        payload: &'a EInvoice,
+    ) -> BoxFuture<'a, Result<EInvoiceRegistration>> {
+        Box::pin(async move {
+            let key = (
+                &payload.seller_dtls.gstin,
+                payload.doc_dtls.typ,
+                &payload.doc_dtls.no,
+            );
+            let irn = (0..4u8)
+                .map(|seed| {
+                    let mut hasher = DefaultHasher::new();
+                    (seed, key).hash(&mut hasher);
+                    format!("{:016x}", hasher.finish())
+                })
+                .collect::<String>();
+            let now = DateTime::now();
+            Ok(EInvoiceRegistration {
+                ack_no: format!("{:015}", now.timestamp_millis()),
+                signed_qr_code: format!("{}{}", STUB_QR_PREFIX, irn),
+                irn,
+                ack_date: now,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+    use crate::billing::local_midnight;
+    use crate::invoicing::invoice_for_usage;
+    use crate::model::{
+        OrganizationAddress, OrganizationPricingTier, OrganizationStatus, OrganizationUsage,
+    };
+    use crate::money::Money;
+
+    fn date(y: i32, m: u32, d: u32) -> DateTime {
+        local_midnight(NaiveDate::from_ymd_opt(y, m, d).unwrap())
+    }
+
+    fn supplier() -> Supplier {
+        Supplier {
+            name: String::from("Auditplus Software"),
+            address: String::from("12 Gandhi Road\nCoimbatore"),
+            city: Some(String::from("Coimbatore")),
+            pin_code: Some(String::from("641001")),
+            gst_no: String::from("33AAACA1234A1Z5"),
+            state: String::from("33"),
+        }
+    }
+
+    fn organization() -> Organization {
+        let billing_address = OrganizationAddress {
+            street: Some(String::from("4 MG Road")),
+            city: Some(String::from("Bengaluru")),
+            pin_code: Some(String::from("560001")),
+            state: Some(String::from("Karnataka")),
+            country: String::from("India"),
+        };
+        Organization {
+            id: ObjectId::new(),
+            name: String::from("acme"),
+            full_name: String::from("Acme Traders"),
+            country: String::from("India"),
+            gst_no: Some(String::from("29AAACB5678B1Z3")),
+            book_begin: date(2026, 1, 1),
+            fp_code: 4,
+            pricing: OrganizationPricingTier::T2,
+            cluster: String::from("c1"),
+            users: Vec::new(),
+            communication_address: billing_address.clone(),
+            billing_address,
+            grace_period: 15,
+            unbilled: false,
+            additions: None,
+            addition_history: Vec::new(),
+            status: OrganizationStatus::Active,
+            fund: 0,
+            wallet_balance: Money::ZERO,
+            owned_by: ObjectId::new(),
+            created_at: date(2026, 1, 1),
+            updated_at: date(2026, 1, 1),
+        }
+    }
+
+    /// The March invoice, issued at the start of April.
+    fn issued(organization: &Organization) -> Invoice {
+        let usage = OrganizationUsage {
+            billing_period: String::from("2026-03-01 - 2026-03-31"),
+            plan: String::from("T2"),
+            base_charge: Money::from_rupees(999),
+            ..Default::default()
+        };
+        let mut invoice = invoice_for_usage(
+            &supplier(),
+            organization,
+            date(2026, 3, 1),
+            date(2026, 4, 1),
+            vec![usage],
+        );
+        invoice.draft = false;
+        invoice.invoice_no = Some(String::from("AP/2026-27/000001"));
+        invoice.issued_at = Some(date(2026, 4, 2));
+        invoice
+    }
+
+    fn error(invoice: &Invoice, organization: &Organization) -> String {
+        build(invoice, organization, &supplier())
+            .unwrap_err()
+            .msg()
+            .clone()
+    }
+
+    #[test]
+    fn an_issued_invoice_is_dated_by_its_issue() {
+        let organization = organization();
+        let payload = build(&issued(&organization), &organization, &supplier()).unwrap();
+        assert_eq!(payload.doc_dtls.no, "AP/2026-27/000001");
+        assert_eq!(payload.doc_dtls.dt, "02/04/2026");
+        assert_eq!(payload.buyer_dtls.stcd, "29");
+        assert_eq!(payload.buyer_dtls.pos.as_deref(), Some("29"));
+        assert_eq!(payload.buyer_dtls.pin, 560001);
+        assert_eq!(payload.seller_dtls.addr1, "12 Gandhi Road");
+        // Karnataka is outside the supplier's state, so the tax is IGST.
+        assert_eq!(payload.item_list.len(), 1);
+        assert_eq!(payload.item_list[0].igst_amt, 179.82);
+        assert_eq!(payload.item_list[0].cgst_amt, 0.0);
+        assert_eq!(payload.val_dtls.tot_inv_val, 1179.0);
+    }
+
+    #[test]
+    fn a_draft_has_no_number_or_issue_date() {
+        let organization = organization();
+        let mut invoice = issued(&organization);
+        invoice.draft = true;
+        invoice.invoice_no = None;
+        invoice.issued_at = None;
+        assert_eq!(
+            error(&invoice, &organization),
+            "E-invoice is missing: invoice number (the invoice is still a draft), issue date"
+        );
+    }
+
+    #[test]
+    fn every_missing_buyer_field_is_reported() {
+        let mut organization = organization();
+        let invoice = issued(&organization);
+        organization.gst_no = Some(String::from("29aaacb5678b1z3"));
+        organization.billing_address.pin_code = Some(String::from("5600"));
+        organization.billing_address.city = None;
+        organization.billing_address.state = None;
+        assert_eq!(
+            error(&invoice, &organization),
+            "E-invoice is missing: buyer GSTIN, buyer pin code, buyer location"
+        );
+        // Without a valid GSTIN there is nothing to take the state from either.
+        organization.gst_no = None;
+        assert!(error(&invoice, &organization).ends_with("buyer location, buyer state"));
+    }
+}
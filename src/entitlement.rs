@@ -0,0 +1,185 @@
+use mongodb::bson::DateTime;
+use serde::Serialize;
+
+use crate::model::{
+    Organization, OrganizationFeatures, OrganizationPricing, OrganizationPricingTier,
+    OrganizationStatus,
+};
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    pub users: usize,
+    pub branches: usize,
+    pub cash_registers: usize,
+    pub warehouses: usize,
+    pub clients: usize,
+    /// Vouchers a month before overage is charged.
+    pub vouchers: usize,
+    /// Storage in MB before overage is charged.
+    pub storage: usize,
+}
+
+/// What an organization may use right now: its plan with bought additions added.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entitlements {
+    pub organization: String,
+    pub tier: OrganizationPricingTier,
+    /// Callers decide how to treat a suspended or deactivated organization.
+    pub status: OrganizationStatus,
+    pub features: Vec<OrganizationFeatures>,
+    pub limits: Limits,
+}
+
+/// Entitlements of `organization` on `plan`, the plan of its tier in effect `at`.
+pub fn entitlements(
+    organization: &Organization,
+    plan: &OrganizationPricing,
+    at: DateTime,
+) -> Entitlements {
+    // The latest counts in effect; additions bought for a later date do not count yet.
+    let additions = organization
+        .addition_timeline()
+        .into_iter()
+        .rfind(|additions| additions.effective_from.is_none_or(|from| from <= at))
+        .map(|additions| plan.usable_additions(additions))
+        .unwrap_or_default();
+    let limits = Limits {
+        users: plan.users + additions.users,
+        branches: plan.branches + additions.branches,
+        cash_registers: plan.cash_registers + additions.cash_registers,
+        warehouses: plan.warehouse + additions.warehouse,
+        clients: plan.clients + additions.clients,
+        vouchers: plan.vouchers,
+        storage: plan.storage,
+    };
+    let mut features = plan.features.clone();
+    // A bought cash register or warehouse unlocks the feature it needs.
+    let unlocked = [
+        (limits.cash_registers, OrganizationFeatures::CashRegister),
+        (limits.warehouses, OrganizationFeatures::Warehouse),
+    ];
+    for (limit, feature) in unlocked {
+        if limit > 0 && !features.contains(&feature) {
+            features.push(feature);
+        }
+    }
+    Entitlements {
+        organization: organization.name.clone(),
+        tier: organization.pricing,
+        status: organization.status,
+        features,
+        limits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+    use crate::model::{OrganizationAddress, OrganizationPricingAdditions};
+    use crate::money::Money;
+
+    /// An organization whose current additions are the last of `additions`, held
+    /// after the others.
+    fn organization(
+        pricing: OrganizationPricingTier,
+        additions: Vec<OrganizationPricingAdditions>,
+    ) -> Organization {
+        let mut additions = additions;
+        let current = additions.pop();
+        Organization {
+            id: ObjectId::new(),
+            name: String::from("acme"),
+            full_name: String::from("Acme Traders"),
+            country: String::from("India"),
+            gst_no: None,
+            book_begin: DateTime::now(),
+            fp_code: 4,
+            pricing,
+            cluster: String::from("c1"),
+            users: Vec::new(),
+            communication_address: OrganizationAddress::with_country(String::from("India")),
+            billing_address: OrganizationAddress::with_country(String::from("India")),
+            grace_period: 15,
+            unbilled: false,
+            additions: current,
+            addition_history: additions,
+            status: OrganizationStatus::Active,
+            fund: 0,
+            wallet_balance: Money::ZERO,
+            owned_by: ObjectId::new(),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn usable_additions_stop_at_the_plan_maximums() {
+        let additions = OrganizationPricingAdditions {
+            branches: 3,
+            users: 40,
+            warehouse: 2,
+            ..Default::default()
+        };
+        // T1 allows one branch, which it already includes.
+        let t1 = OrganizationPricing::t1().usable_additions(additions);
+        assert_eq!((t1.branches, t1.users, t1.warehouse), (0, 40, 2));
+        let t2 = OrganizationPricing::t2().usable_additions(additions);
+        assert_eq!(t2.branches, 1);
+        let t5 = OrganizationPricing::t5().usable_additions(additions);
+        assert_eq!(t5.branches, 3);
+    }
+
+    #[test]
+    fn additions_raise_limits_and_unlock_features() {
+        let plan = OrganizationPricing::t2();
+        let additions = OrganizationPricingAdditions {
+            branches: 3,
+            users: 4,
+            warehouse: 1,
+            ..Default::default()
+        };
+        let organization = organization(OrganizationPricingTier::T2, vec![additions]);
+        let entitlements = entitlements(&organization, &plan, DateTime::now());
+        assert_eq!(entitlements.limits.users, plan.users + 4);
+        assert_eq!(entitlements.limits.branches, 2);
+        assert_eq!(entitlements.limits.warehouses, plan.warehouse + 1);
+        assert!(entitlements
+            .features
+            .contains(&OrganizationFeatures::Warehouse));
+        assert_eq!(
+            entitlements
+                .features
+                .contains(&OrganizationFeatures::CashRegister),
+            plan.features.contains(&OrganizationFeatures::CashRegister)
+        );
+    }
+
+    #[test]
+    fn additions_bought_for_a_later_date_do_not_count_yet() {
+        let plan = OrganizationPricing::t5();
+        let now = DateTime::now();
+        let later = DateTime::from_millis(now.timestamp_millis() + 86_400_000);
+        let held = OrganizationPricingAdditions {
+            users: 2,
+            ..Default::default()
+        };
+        let bought = OrganizationPricingAdditions {
+            users: 5,
+            effective_from: Some(later),
+            ..Default::default()
+        };
+        let organization = organization(OrganizationPricingTier::T5, vec![held, bought]);
+        assert_eq!(
+            entitlements(&organization, &plan, now).limits.users,
+            plan.users + 2
+        );
+        assert_eq!(
+            entitlements(&organization, &plan, later).limits.users,
+            plan.users + 5
+        );
+    }
+}
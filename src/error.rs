@@ -2,6 +2,15 @@ use std::{error, fmt, result};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Whether a write failed because it would break a unique index.
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error))
+            if write_error.code == 11000
+    )
+}
+
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum ErrorKind {
@@ -45,7 +54,8 @@ impl From<mongodb::bson::oid::Error> for ErrorKind {
 pub struct Error {
     msg: String,
     code: &'static str,
-    kind: ErrorKind,
+    /// Boxed, since the database errors it can hold would make every `Result` large.
+    kind: Box<ErrorKind>,
 }
 
 impl Error {
@@ -53,7 +63,7 @@ impl Error {
         Error {
             msg: msg.into(),
             code: "NA",
-            kind: kind.into(),
+            kind: Box::new(kind.into()),
         }
     }
 
@@ -65,7 +75,7 @@ impl Error {
         Error {
             msg: msg.into(),
             code,
-            kind: kind.into(),
+            kind: Box::new(kind.into()),
         }
     }
 
@@ -74,12 +84,50 @@ impl Error {
     }
 
     pub fn code(&self) -> &str {
-        &self.code
+        self.code
     }
 
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
+
+    /// Whether the database rejected a write that would break a unique index.
+    pub fn is_duplicate_key(&self) -> bool {
+        matches!(self.kind.as_ref(), ErrorKind::DatabaseError(err) if is_duplicate_key(err))
+    }
+
+    /// Whether the failed transaction can be retried from the start.
+    pub fn is_transient_transaction(&self) -> bool {
+        matches!(
+            self.kind.as_ref(),
+            ErrorKind::DatabaseError(err)
+                if err.contains_label(mongodb::error::TRANSIENT_TRANSACTION_ERROR)
+        )
+    }
+}
+
+impl From<mongodb::error::Error> for Error {
+    fn from(err: mongodb::error::Error) -> Self {
+        Error::new(err.to_string(), err)
+    }
+}
+
+impl From<mongodb::bson::de::Error> for Error {
+    fn from(err: mongodb::bson::de::Error) -> Self {
+        Error::new(err.to_string(), err)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for Error {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        Error::new(err.to_string(), err)
+    }
+}
+
+impl From<mongodb::bson::oid::Error> for Error {
+    fn from(err: mongodb::bson::oid::Error) -> Self {
+        Error::new(err.to_string(), err)
+    }
 }
 
 impl fmt::Display for Error {
@@ -90,7 +138,7 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match &self.kind {
+        match self.kind.as_ref() {
             ErrorKind::Internal => None,
             ErrorKind::InvalidData => None,
             ErrorKind::NotFound => None,
@@ -122,7 +170,7 @@ impl ErrorResponse {
 
 impl actix_web::ResponseError for Error {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        match self.kind {
+        match *self.kind {
             ErrorKind::Internal => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ErrorKind::InvalidData => actix_web::http::StatusCode::BAD_REQUEST,
             ErrorKind::UnAuthorized => actix_web::http::StatusCode::UNAUTHORIZED,
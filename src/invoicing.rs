@@ -0,0 +1,666 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, from_document, oid::ObjectId, to_bson, DateTime, Document},
+    options::{FindOneAndUpdateOptions, FindOneOptions, ReplaceOptions, ReturnDocument},
+    Client, ClientSession, Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::billing::{
+    calculate_usage, end_of_day, explain_usage, local_date, local_midnight, BillingPeriod,
+};
+use crate::catalog::PricingCatalog;
+use crate::date::Date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::lease::{LeaseConfig, BILLING_LEASE};
+use crate::metering::{usage_records, usage_records_with_session};
+use crate::model::{
+    BillingRun, BillingRunStatus, BillingSkipReason, FailedOrganization, Invoice,
+    InvoicedOrganization, Organization, OrganizationPricingTier, OrganizationStatus,
+    OrganizationUsage, PaidStatus, SkippedOrganization, UsageRecord,
+};
+use crate::money::{Money, Rounding};
+use crate::sequence::Numberings;
+use crate::supplier::Supplier;
+use crate::tax::{
+    compute_tax, invoice_supply_type, place_of_supply, supply_type, SupplyType, GST_RATE,
+};
+use crate::transaction;
+use crate::wallet;
+
+/// Why billing runs pass over `organization`, if they do.
+pub fn skip_reason(organization: &Organization) -> Option<BillingSkipReason> {
+    if organization.unbilled {
+        return Some(BillingSkipReason::Unbilled);
+    }
+    match (organization.status, organization.pricing) {
+        (OrganizationStatus::Suspended, _) => Some(BillingSkipReason::Suspended),
+        (OrganizationStatus::Deactivated, _) => Some(BillingSkipReason::Deactivated),
+        (OrganizationStatus::Active, OrganizationPricingTier::Free) => {
+            Some(BillingSkipReason::Free)
+        }
+        (OrganizationStatus::Active, _) => None,
+    }
+}
+
+fn failure(organization: String, err: &Error) -> FailedOrganization {
+    FailedOrganization {
+        organization,
+        code: err.code().to_string(),
+        message: err.msg().clone(),
+    }
+}
+
+/// Records a queued run billing through `to_date`, to be started by [`execute`].
+pub async fn queue(db: &Database, to_date: DateTime) -> Result<BillingRun> {
+    let run = BillingRun {
+        id: ObjectId::new(),
+        status: BillingRunStatus::Queued,
+        billed_through: to_date,
+        total: 0,
+        invoiced: Vec::new(),
+        skipped: Vec::new(),
+        failed: Vec::new(),
+        error: None,
+        cancel_requested: false,
+        started_at: DateTime::now(),
+        finished_at: None,
+    };
+    BillingRun::collection(db).insert_one(&run, None).await?;
+    Ok(run)
+}
+
+/// Bills every organization through `to_date` under the billing lease and records
+/// the run. A failure is reported against its organization and does not stop the
+/// others from billing.
+pub async fn run(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    leases: &LeaseConfig,
+    to_date: DateTime,
+) -> Result<BillingRun> {
+    let run = queue(db, to_date).await?;
+    execute_leased(client, db, supplier, numberings, leases, run.id).await
+}
+
+/// Works through the queued run `id`, saving progress after every organization.
+pub async fn execute(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    id: ObjectId,
+) -> Result<BillingRun> {
+    let status = match bill_all(client, db, supplier, numberings, id).await {
+        Ok(status) => finish(db, id, status, None).await,
+        Err(err) => finish(db, id, BillingRunStatus::Failed, Some(&err)).await,
+    };
+    status?;
+    find_run(db, id).await
+}
+
+/// [`execute`] while holding the billing lease. When the lease is already held,
+/// or is lost and `execute` is stopped midway, the run is closed as failed here,
+/// since `execute` never got to close it.
+pub async fn execute_leased(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    leases: &LeaseConfig,
+    id: ObjectId,
+) -> Result<BillingRun> {
+    let work = execute(client, db, supplier, numberings, id);
+    let result = leases.run(db, BILLING_LEASE, work).await;
+    if let Err(err) = &result {
+        abandon(db, id, err).await;
+    }
+    result
+}
+
+/// Closes the run `id` as failed with `err`, unless it has already finished.
+pub async fn abandon(db: &Database, id: ObjectId, err: &Error) {
+    if let Err(err) = finish(db, id, BillingRunStatus::Failed, Some(err)).await {
+        println!("Billing run {} could not be closed: {}", id, err);
+    }
+}
+
+pub async fn find_run(db: &Database, id: ObjectId) -> Result<BillingRun> {
+    BillingRun::collection(db)
+        .find_one(doc! {"_id": id}, None)
+        .await?
+        .ok_or_else(|| Error::new("Billing run not found", ErrorKind::NotFound))
+}
+
+/// Asks a queued or running billing run to stop.
+pub async fn cancel(db: &Database, id: ObjectId) -> Result<BillingRun> {
+    let result = BillingRun::collection(db)
+        .update_one(
+            doc! {
+                "_id": id,
+                "status": {"$in": [BillingRunStatus::Queued, BillingRunStatus::Running]},
+            },
+            doc! {"$set": {"cancelRequested": true}},
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        find_run(db, id).await?;
+        return Err(Error::new(
+            "Billing run has already finished",
+            ErrorKind::LogicalError,
+        ));
+    }
+    find_run(db, id).await
+}
+
+/// Ends the run `id`, with `error` when the run as a whole failed. A run that
+/// has already finished keeps its outcome.
+pub async fn finish(
+    db: &Database,
+    id: ObjectId,
+    status: BillingRunStatus,
+    error: Option<&Error>,
+) -> Result<()> {
+    BillingRun::collection(db)
+        .update_one(
+            doc! {
+                "_id": id,
+                "status": {"$in": [BillingRunStatus::Queued, BillingRunStatus::Running]},
+            },
+            doc! {"$set": {
+                "status": status,
+                "error": error.map(|err| err.msg().clone()),
+                "finishedAt": DateTime::now(),
+            }},
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Adds an organization's outcome to `field` of the run, returning whether the
+/// run has been asked to stop.
+async fn record_outcome(
+    db: &Database,
+    id: ObjectId,
+    field: &str,
+    outcome: impl serde::Serialize,
+) -> Result<bool> {
+    let run = BillingRun::collection(db)
+        .find_one_and_update(
+            doc! {"_id": id},
+            doc! {"$push": {field: to_bson(&outcome)?}},
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await?;
+    Ok(run.map(|run| run.cancel_requested).unwrap_or(true))
+}
+
+async fn bill_all(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    id: ObjectId,
+) -> Result<BillingRunStatus> {
+    let run = find_run(db, id).await?;
+    if run.cancel_requested {
+        return Ok(BillingRunStatus::Cancelled);
+    }
+    let to_date = run.billed_through;
+    let catalog = PricingCatalog::load(db).await?;
+    // Read raw documents so one malformed organization fails alone.
+    let documents = Organization::collection(db)
+        .clone_with_type::<Document>()
+        .find(doc! {}, None)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?;
+    BillingRun::collection(db)
+        .update_one(
+            doc! {"_id": id},
+            doc! {"$set": {
+                "status": BillingRunStatus::Running,
+                "total": documents.len() as i64,
+            }},
+            None,
+        )
+        .await?;
+    for document in documents {
+        let organization = match from_document::<Organization>(document.clone()) {
+            Ok(organization) => organization,
+            Err(err) => {
+                let name = match (document.get_str("name"), document.get_object_id("_id")) {
+                    (Ok(name), _) => name.to_string(),
+                    (_, Ok(id)) => id.to_hex(),
+                    _ => String::from("-"),
+                };
+                if record_outcome(db, id, "failed", failure(name, &err.into())).await? {
+                    return Ok(BillingRunStatus::Cancelled);
+                }
+                continue;
+            }
+        };
+        let stop = if let Some(reason) = skip_reason(&organization) {
+            let skipped = SkippedOrganization {
+                organization: organization.name,
+                reason,
+            };
+            record_outcome(db, id, "skipped", skipped).await?
+        } else {
+            match bill_organization(
+                client,
+                db,
+                supplier,
+                numberings,
+                &catalog,
+                &organization,
+                to_date,
+            )
+            .await
+            {
+                Ok(Some(invoice)) => {
+                    let invoiced = InvoicedOrganization {
+                        organization: organization.name,
+                        invoice: invoice.id,
+                        total_value: invoice.total_value,
+                    };
+                    record_outcome(db, id, "invoiced", invoiced).await?
+                }
+                Ok(None) => {
+                    let skipped = SkippedOrganization {
+                        organization: organization.name,
+                        reason: BillingSkipReason::UpToDate,
+                    };
+                    record_outcome(db, id, "skipped", skipped).await?
+                }
+                Err(err) => {
+                    record_outcome(db, id, "failed", failure(organization.name, &err)).await?
+                }
+            }
+        };
+        if stop {
+            return Ok(BillingRunStatus::Cancelled);
+        }
+    }
+    Ok(BillingRunStatus::Completed)
+}
+
+/// Finalizes the previous draft of `organization`, pays it from the wallet and
+/// writes the draft for the period ending at `to_date`, all in one transaction.
+/// The transaction is rolled back on failure and retried when the database
+/// reports the failure as transient.
+pub async fn bill_organization(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    catalog: &PricingCatalog,
+    organization: &Organization,
+    to_date: DateTime,
+) -> Result<Option<Invoice>> {
+    let mut attempt = 1;
+    loop {
+        let mut session = transaction::start(client).await?;
+        let result = bill_with_session(
+            db,
+            &mut session,
+            supplier,
+            numberings,
+            catalog,
+            organization,
+            to_date,
+        )
+        .await;
+        match transaction::finish(&mut session, result).await {
+            // A concurrent run wrote this period first.
+            Err(err) if err.is_duplicate_key() => return Ok(None),
+            Err(err) if transaction::should_retry(&err, attempt) => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn bill_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    catalog: &PricingCatalog,
+    organization: &Organization,
+    to_date: DateTime,
+) -> Result<Option<Invoice>> {
+    let latest = Invoice::collection(db)
+        .find_one_with_session(
+            doc! {"organization": &organization.name},
+            FindOneOptions::builder().sort(doc! {"date": -1}).build(),
+            session,
+        )
+        .await?;
+    let mut from_date = organization.book_begin;
+    if let Some(mut invoice) = latest {
+        if invoice.date >= to_date {
+            // This period was already generated; a draft is regenerated in place.
+            from_date = match (invoice.draft, invoice.period_start) {
+                (true, Some(period_start)) => period_start,
+                _ => invoice.date,
+            };
+        } else {
+            from_date = invoice.date;
+            if let (true, Some(period_start)) = (invoice.draft, invoice.period_start) {
+                // Usage reported after the draft was written, such as the last
+                // day's events of its month, is billed on the draft it belongs to.
+                if let Some(redrafted) = make_invoice(
+                    db,
+                    session,
+                    supplier,
+                    catalog,
+                    organization,
+                    period_start,
+                    invoice.date,
+                )
+                .await?
+                {
+                    invoice = redrafted;
+                }
+            }
+            if invoice.draft && invoice.total_value.is_positive() {
+                // Numbered in the financial year it is issued in, not the one its
+                // period ended in.
+                let issued_at = DateTime::now();
+                invoice.draft = false;
+                invoice.invoice_no = Some(
+                    numberings
+                        .invoice
+                        .next_with_session(db, session, issued_at)
+                        .await?,
+                );
+                invoice.issued_at = Some(issued_at);
+                invoice.updated_at = issued_at;
+                Invoice::collection(db)
+                    .replace_one_with_session(doc! {"_id": invoice.id}, &invoice, None, session)
+                    .await?;
+                wallet::apply_with_session(db, session, organization, &invoice).await?;
+            }
+        }
+    }
+    make_invoice(
+        db,
+        session,
+        supplier,
+        catalog,
+        organization,
+        from_date,
+        to_date,
+    )
+    .await
+}
+
+/// Computes the draft invoice of `organization` for `[from_date, to_date)` without
+/// writing it.
+pub fn draft_invoice(
+    supplier: &Supplier,
+    catalog: &PricingCatalog,
+    organization: &Organization,
+    metered: &[UsageRecord],
+    from_date: DateTime,
+    to_date: DateTime,
+) -> Invoice {
+    let organization_usage = calculate_usage(
+        from_date,
+        to_date,
+        organization.pricing,
+        &organization.addition_timeline(),
+        catalog,
+        metered,
+    );
+    invoice_for_usage(
+        supplier,
+        organization,
+        from_date,
+        to_date,
+        organization_usage,
+    )
+}
+
+/// Prices and taxes `organization_usage` as the draft invoice for `[from_date, to_date)`.
+pub fn invoice_for_usage(
+    supplier: &Supplier,
+    organization: &Organization,
+    from_date: DateTime,
+    to_date: DateTime,
+    organization_usage: Vec<OrganizationUsage>,
+) -> Invoice {
+    let billing_period = BillingPeriod {
+        start: local_date(from_date),
+        end: local_date(to_date),
+    }
+    .label();
+    let organization = organization.clone();
+    let service_value: Money = organization_usage.iter().map(|usage| usage.amount()).sum();
+    let place_of_supply = place_of_supply(
+        &organization.billing_address,
+        organization.gst_no.as_deref(),
+    );
+    let tax = compute_tax(
+        service_value,
+        GST_RATE,
+        supply_type(&supplier.state, place_of_supply.as_deref()),
+    );
+    let tax_value = tax.total();
+    let total_value = service_value + tax_value;
+    let now = DateTime::now();
+    Invoice {
+        id: ObjectId::new(),
+        invoice_no: None,
+        date: to_date,
+        period_start: Some(from_date),
+        billing_period: Some(billing_period),
+        billed_to: organization.full_name,
+        organization: organization.name,
+        organization_usage,
+        service_value,
+        place_of_supply,
+        tax_ratio: tax.tax_ratio,
+        cgst_value: tax.cgst_value,
+        sgst_value: tax.sgst_value,
+        igst_value: tax.igst_value,
+        tax_value,
+        total_value,
+        rounded_value: total_value.round_to_rupee(Rounding::HalfUp),
+        draft: true,
+        issued_at: None,
+        paid_status: PaidStatus::Unpaid,
+        amount_paid: Money::ZERO,
+        credited_value: Money::ZERO,
+        e_invoice: None,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewQuery {
+    /// Last day to bill, inclusive.
+    pub to: Date,
+    /// Tier to move to from today, to quote the cost of an upgrade.
+    pub tier: Option<OrganizationPricingTier>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineExplanation {
+    /// Position of the line in the invoice's `organization_usage`.
+    pub line: usize,
+    pub description: String,
+    pub amount: Money,
+    pub explanation: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoicePreview {
+    pub invoice: Invoice,
+    pub lines: Vec<LineExplanation>,
+    pub tax: String,
+}
+
+/// Computes the draft the next run would write for `organization` if it billed
+/// through `query.to`, without writing anything. The period starts where the
+/// latest invoice, draft or not, ends, as it does for a run.
+pub async fn preview(
+    db: &Database,
+    supplier: &Supplier,
+    organization: &Organization,
+    query: &PreviewQuery,
+) -> Result<InvoicePreview> {
+    let from_date = next_period_start(db, organization).await?;
+    let to_date = end_of_day(query.to.value())?;
+    if from_date >= to_date {
+        return Err(Error::new(
+            format!(
+                "{} is already billed through {}",
+                organization.name, query.to
+            ),
+            ErrorKind::InvalidData,
+        ));
+    }
+    let catalog = PricingCatalog::load(db).await?;
+    let metered = usage_records(db, &organization.name, from_date, to_date).await?;
+    let additions = organization.addition_timeline();
+    let explained = match query.tier {
+        Some(tier) if tier != organization.pricing => {
+            let today = local_midnight(local_date(DateTime::now()));
+            let switch = today.clamp(from_date, to_date);
+            let mut explained = explain_usage(
+                from_date,
+                switch,
+                organization.pricing,
+                &additions,
+                &catalog,
+                &metered,
+            );
+            explained.extend(explain_usage(
+                switch, to_date, tier, &additions, &catalog, &metered,
+            ));
+            explained
+        }
+        _ => explain_usage(
+            from_date,
+            to_date,
+            organization.pricing,
+            &additions,
+            &catalog,
+            &metered,
+        ),
+    };
+    let (usage, explanations): (Vec<_>, Vec<_>) = explained.into_iter().unzip();
+    let invoice = invoice_for_usage(supplier, organization, from_date, to_date, usage);
+    let lines = invoice
+        .organization_usage
+        .iter()
+        .zip(explanations)
+        .enumerate()
+        .map(|(line, (usage, explanation))| LineExplanation {
+            line,
+            description: usage.description(),
+            amount: usage.amount(),
+            explanation,
+        })
+        .collect();
+    let tax = match invoice_supply_type(&invoice) {
+        SupplyType::IntraState => format!(
+            "CGST {}% ({}) and SGST {}% ({}) on {}, as the place of supply is in the supplier's state",
+            invoice.tax_ratio / 2.0,
+            invoice.cgst_value,
+            invoice.tax_ratio / 2.0,
+            invoice.sgst_value,
+            invoice.service_value
+        ),
+        SupplyType::InterState => format!(
+            "IGST {}% ({}) on {}, as the place of supply is outside the supplier's state",
+            invoice.tax_ratio, invoice.igst_value, invoice.service_value
+        ),
+    };
+    Ok(InvoicePreview {
+        invoice,
+        lines,
+        tax,
+    })
+}
+
+/// Latest invoice of `organization`, draft or finalized.
+pub async fn latest_invoice(db: &Database, organization: &Organization) -> Result<Option<Invoice>> {
+    Ok(Invoice::collection(db)
+        .find_one(
+            doc! {"organization": &organization.name},
+            FindOneOptions::builder().sort(doc! {"date": -1}).build(),
+        )
+        .await?)
+}
+
+/// Start of the period the next run drafts for `organization`: the end of its
+/// latest invoice, draft or finalized. An open draft is finalized over its own
+/// period instead.
+pub async fn next_period_start(db: &Database, organization: &Organization) -> Result<DateTime> {
+    Ok(latest_invoice(db, organization)
+        .await?
+        .map(|invoice| invoice.date)
+        .unwrap_or(organization.book_begin))
+}
+
+/// Writes the draft invoice for `[from_date, to_date)`. An existing draft for the
+/// same period is updated in place; a finalized one is left alone.
+async fn make_invoice(
+    db: &Database,
+    session: &mut ClientSession,
+    supplier: &Supplier,
+    catalog: &PricingCatalog,
+    organization: &Organization,
+    from_date: DateTime,
+    to_date: DateTime,
+) -> Result<Option<Invoice>> {
+    if from_date >= to_date {
+        return Ok(None);
+    }
+    let metered =
+        usage_records_with_session(db, session, &organization.name, from_date, to_date).await?;
+    let mut invoice = draft_invoice(
+        supplier,
+        catalog,
+        organization,
+        &metered,
+        from_date,
+        to_date,
+    );
+    let existing = Invoice::collection(db)
+        .find_one_with_session(
+            doc! {"organization": &organization.name, "billingPeriod": &invoice.billing_period},
+            None,
+            session,
+        )
+        .await?;
+    match existing {
+        Some(existing) if !existing.draft => return Ok(None),
+        Some(existing) => {
+            invoice.id = existing.id;
+            invoice.created_at = existing.created_at;
+        }
+        None => {}
+    }
+    Invoice::collection(db)
+        .replace_one_with_session(
+            doc! {"_id": invoice.id, "draft": true},
+            &invoice,
+            ReplaceOptions::builder().upsert(true).build(),
+            session,
+        )
+        .await?;
+    Ok(Some(invoice))
+}
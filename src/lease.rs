@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::UpdateOptions,
+    Database,
+};
+
+use crate::error::{is_duplicate_key, Error, ErrorKind, Result};
+use crate::model::Lease;
+
+/// Lease taken by billing runs, whether started by hand or by the scheduler.
+pub const BILLING_LEASE: &str = "BILLING";
+
+/// Error code when the lease is already held.
+pub const LEASE_HELD: &str = "LEASE_HELD";
+
+/// How this instance identifies itself when holding leases, and for how long a
+/// lease lasts without renewal.
+#[derive(Debug, Clone)]
+pub struct LeaseConfig {
+    pub holder: String,
+    pub ttl: Duration,
+}
+
+impl LeaseConfig {
+    /// Reads `LEASE_TTL_SECS`, 60 by default. The holder id is unique per process.
+    pub fn from_env() -> LeaseConfig {
+        let ttl = std::env::var("LEASE_TTL_SECS")
+            .map(|ttl| ttl.parse::<u64>().expect("LEASE_TTL_SECS must be seconds"))
+            .unwrap_or(60);
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| String::from("localhost"));
+        LeaseConfig {
+            holder: format!("{}/{}", host, ObjectId::new().to_hex()),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    fn expires_at(&self, now: DateTime) -> DateTime {
+        DateTime::from_millis(now.timestamp_millis() + self.ttl.as_millis() as i64)
+    }
+
+    /// Whether some instance, this one included, currently holds the lease `name`.
+    pub async fn is_held(&self, db: &Database, name: &str) -> Result<bool> {
+        let held = Lease::collection(db)
+            .count_documents(
+                doc! {"_id": name, "expiresAt": {"$gt": DateTime::now()}},
+                None,
+            )
+            .await?;
+        Ok(held > 0)
+    }
+
+    /// Takes the lease `name` if it is free or has expired.
+    pub async fn acquire(&self, db: &Database, name: &str) -> Result<bool> {
+        let now = DateTime::now();
+        let taken = Lease::collection(db)
+            .update_one(
+                doc! {"_id": name, "expiresAt": {"$lte": now}},
+                doc! {
+                    "$set": {
+                        "holder": &self.holder,
+                        "expiresAt": self.expires_at(now),
+                        "acquiredAt": now,
+                        "renewedAt": now,
+                    },
+                },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await;
+        match taken {
+            Ok(_) => Ok(true),
+            // The lease exists and is held, so the upsert collided.
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Extends a lease we hold; false if it has been lost to another instance.
+    pub async fn renew(&self, db: &Database, name: &str) -> Result<bool> {
+        let now = DateTime::now();
+        let result = Lease::collection(db)
+            .update_one(
+                doc! {"_id": name, "holder": &self.holder},
+                doc! {"$set": {"expiresAt": self.expires_at(now), "renewedAt": now}},
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    pub async fn release(&self, db: &Database, name: &str) -> Result<()> {
+        Lease::collection(db)
+            .delete_one(doc! {"_id": name, "holder": &self.holder}, None)
+            .await?;
+        Ok(())
+    }
+
+    /// Runs `work` while holding the lease `name`, renewing it as it goes. Fails
+    /// with [`LEASE_HELD`] if it is already held, and stops `work` if the
+    /// lease is lost midway.
+    pub async fn run<T>(
+        &self,
+        db: &Database,
+        name: &str,
+        work: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if !self.acquire(db, name).await? {
+            return Err(Error::with_code(
+                format!("{} is already running", name),
+                LEASE_HELD,
+                ErrorKind::LogicalError,
+            ));
+        }
+        let renewals = async {
+            loop {
+                actix_web::rt::time::sleep(self.ttl / 3).await;
+                // A failed renewal is retried; the lease only counts as lost once
+                // it has expired and someone else has taken it.
+                if let Ok(false) = self.renew(db, name).await {
+                    return Error::with_code(
+                        format!("Lost the {} lease", name),
+                        "LEASE_LOST",
+                        ErrorKind::Internal,
+                    );
+                }
+            }
+        };
+        let result = match future::select(Box::pin(work), Box::pin(renewals)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((lost, _)) => return Err(lost),
+        };
+        self.release(db, name).await?;
+        result
+    }
+}
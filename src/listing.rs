@@ -0,0 +1,250 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    options::FindOptions,
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::billing::{end_of_day, local_midnight};
+use crate::date::Date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{Invoice, PaidStatus};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InvoiceSort {
+    #[default]
+    Date,
+    Amount,
+    CreatedAt,
+}
+
+impl InvoiceSort {
+    fn field(&self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::Amount => "roundedValue",
+            Self::CreatedAt => "createdAt",
+        }
+    }
+
+    fn key(&self, invoice: &Invoice) -> i64 {
+        match self {
+            Self::Date => invoice.date.timestamp_millis(),
+            Self::Amount => invoice.rounded_value.paise(),
+            Self::CreatedAt => invoice.created_at.timestamp_millis(),
+        }
+    }
+
+    fn value(&self, key: i64) -> Bson {
+        match self {
+            Self::Date | Self::CreatedAt => Bson::DateTime(DateTime::from_millis(key)),
+            Self::Amount => Bson::Int64(key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceQuery {
+    /// Organization name.
+    pub organization: Option<String>,
+    /// First invoice date included.
+    pub from: Option<Date>,
+    /// Last invoice date included.
+    pub to: Option<Date>,
+    pub draft: Option<bool>,
+    pub paid_status: Option<PaidStatus>,
+    /// Lowest rounded total included, in paise.
+    pub min_amount: Option<i64>,
+    /// Highest rounded total included, in paise.
+    pub max_amount: Option<i64>,
+    #[serde(default)]
+    pub sort: InvoiceSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<i64>,
+    /// `nextCursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoicePage {
+    pub invoices: Vec<Invoice>,
+    /// Pass as `cursor` for the next page; absent on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+fn filter(query: &InvoiceQuery) -> Result<Document> {
+    let mut filter = doc! {};
+    if let Some(organization) = &query.organization {
+        filter.insert("organization", organization);
+    }
+    let mut date = doc! {};
+    if let Some(from) = query.from {
+        date.insert("$gte", local_midnight(from.value()));
+    }
+    if let Some(to) = query.to {
+        date.insert("$lt", end_of_day(to.value())?);
+    }
+    if !date.is_empty() {
+        filter.insert("date", date);
+    }
+    if let Some(draft) = query.draft {
+        filter.insert("draft", draft);
+    }
+    if let Some(paid_status) = query.paid_status {
+        filter.insert("paidStatus", paid_status);
+    }
+    let mut amount = doc! {};
+    if let Some(min_amount) = query.min_amount {
+        amount.insert("$gte", min_amount);
+    }
+    if let Some(max_amount) = query.max_amount {
+        amount.insert("$lte", max_amount);
+    }
+    if !amount.is_empty() {
+        filter.insert("roundedValue", amount);
+    }
+    Ok(filter)
+}
+
+/// Cursors are the sort key and id of the last invoice on a page, so a page
+/// stays stable when invoices are added ahead of it.
+fn parse_cursor(cursor: &str) -> Result<(i64, ObjectId)> {
+    let invalid = || Error::new("Invalid cursor", ErrorKind::InvalidData);
+    let (key, id) = cursor.split_once('_').ok_or_else(invalid)?;
+    let key = key.parse::<i64>().map_err(|_| invalid())?;
+    let id = ObjectId::parse_str(id).map_err(|_| invalid())?;
+    Ok((key, id))
+}
+
+pub async fn list_invoices(db: &Database, query: &InvoiceQuery) -> Result<InvoicePage> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let field = query.sort.field();
+    let (direction, after) = match query.order {
+        SortOrder::Asc => (1, "$gt"),
+        SortOrder::Desc => (-1, "$lt"),
+    };
+    let mut filter = filter(query)?;
+    if let Some(cursor) = &query.cursor {
+        let (key, id) = parse_cursor(cursor)?;
+        let key = query.sort.value(key);
+        filter = doc! {"$and": [filter, {"$or": [
+            {field: {after: key.clone()}},
+            {field: key, "_id": {after: id}},
+        ]}]};
+    }
+    let opts = FindOptions::builder()
+        .sort(doc! {field: direction, "_id": direction})
+        .limit(limit + 1)
+        .build();
+    let mut invoices = Invoice::collection(db)
+        .find(filter, opts)
+        .await?
+        .try_collect::<Vec<Invoice>>()
+        .await?;
+    let next_cursor = if invoices.len() as i64 > limit {
+        invoices.truncate(limit as usize);
+        invoices
+            .last()
+            .map(|last| format!("{}_{}", query.sort.key(last), last.id.to_hex()))
+    } else {
+        None
+    };
+    Ok(InvoicePage {
+        invoices,
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn query() -> InvoiceQuery {
+        InvoiceQuery {
+            organization: None,
+            from: None,
+            to: None,
+            draft: None,
+            paid_status: None,
+            min_amount: None,
+            max_amount: None,
+            sort: InvoiceSort::default(),
+            order: SortOrder::default(),
+            limit: None,
+            cursor: None,
+        }
+    }
+
+    #[test]
+    fn a_cursor_is_the_sort_key_and_id() {
+        let id = ObjectId::new();
+        assert_eq!(
+            parse_cursor(&format!("1775001600000_{}", id.to_hex())).unwrap(),
+            (1_775_001_600_000, id)
+        );
+        assert_eq!(
+            parse_cursor(&format!("-250_{}", id.to_hex())).unwrap(),
+            (-250, id)
+        );
+    }
+
+    #[test]
+    fn a_malformed_cursor_is_invalid_input() {
+        let id = ObjectId::new().to_hex();
+        for cursor in [
+            String::new(),
+            String::from("1775001600000"),
+            format!("soon_{}", id),
+            String::from("1775001600000_not-an-id"),
+        ] {
+            let err = parse_cursor(&cursor).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::InvalidData), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn date_filters_include_the_whole_last_day() {
+        let mut query = query();
+        query.from = Some("2026-04-01".parse().unwrap());
+        query.to = Some("2026-04-30".parse().unwrap());
+        query.min_amount = Some(100);
+        let built = filter(&query).unwrap();
+        let day = |y, m, d| local_midnight(NaiveDate::from_ymd_opt(y, m, d).unwrap());
+        assert_eq!(
+            built.get_document("date").unwrap(),
+            &doc! {"$gte": day(2026, 4, 1), "$lt": day(2026, 5, 1)}
+        );
+        assert_eq!(
+            built.get_document("roundedValue").unwrap(),
+            &doc! {"$gte": 100_i64}
+        );
+        // The last day chrono can represent has no day after it.
+        query.to = Some(
+            NaiveDate::MAX
+                .format("%Y-%m-%d")
+                .to_string()
+                .parse()
+                .unwrap(),
+        );
+        assert!(filter(&query).is_err());
+    }
+}
@@ -1,87 +1,381 @@
-use actix_web::{get, web, App, HttpResponse, HttpServer};
-use chrono::NaiveDate;
-use futures::TryStreamExt;
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
 use mongodb::{
-    bson::{doc, DateTime, Document},
-    options::{ClientOptions, FindOptions},
-    Client, Collection, Database,
+    bson::{doc, oid::ObjectId, to_bson, DateTime},
+    options::ClientOptions,
+    Client, Database,
 };
 
-// pub mod date;
+pub mod auth;
+pub mod billing;
+pub mod catalog;
+pub mod credit_note;
+pub mod date;
+pub mod dunning;
+pub mod einvoice;
+pub mod entitlement;
 pub mod error;
+pub mod invoicing;
+pub mod lease;
+pub mod listing;
+pub mod metering;
+pub mod migration;
 pub mod model;
+pub mod money;
+pub mod payment;
+pub mod pdf;
+pub mod scheduler;
+pub mod sequence;
+pub mod summary;
+pub mod supplier;
+pub mod tax;
+pub mod transaction;
+pub mod wallet;
 
-// use date::Date;
-use error::{Error, Result};
-use model::{
-    Invoice, Organization, OrganizationPricing, OrganizationPricingTier, OrganizationUsage,
-};
+use auth::ApiKeys;
+use billing::billing_cutoff;
+use catalog::{PricingCatalog, PricingVersionRequest};
+use credit_note::CreditNoteRequest;
+use dunning::{DunningConfig, Notifier};
+use einvoice::IrpClient;
+use error::{Error, ErrorKind, Result};
+use invoicing::PreviewQuery;
+use lease::{LeaseConfig, BILLING_LEASE, LEASE_HELD};
+use listing::InvoiceQuery;
+use metering::UsageEventBatch;
+use model::{BillingRun, Invoice, Organization, OrganizationPricingTier};
+use payment::PaymentRequest;
+use scheduler::Schedule;
+use sequence::Numberings;
+use supplier::Supplier;
+use wallet::TopUpRequest;
 
 #[get("/generate_invoice")]
-pub async fn generate_invoice(db: web::Data<Database>) -> Result<HttpResponse> {
-    let organizations = Organization::collection(&db)
-        .find(doc! {}, None)
-        .await?
-        .try_collect::<Vec<Organization>>()
-        .await?;
-    for organization in organizations {
-        let find_opts = FindOptions::builder()
-            .sort(doc! {"date": -1})
-            .limit(1)
-            .build();
-        let mut invoices = Invoice::collection(&db)
-            .find(doc! {"name": organization.clone().name}, find_opts)
-            .await?
-            .try_collect::<Vec<Invoice>>()
-            .await?;
-        let mut from_date = organization.clone().book_begin;
-        let to_date = DateTime::now();
-        for invoice in invoices.iter_mut() {
-            if invoice.draft && invoice.total_value > 0.0 {
-                invoice.draft = false;
-            }
-            from_date = invoice.date;
-            Invoice::collection(&db)
-                .replace_one(doc! {"_id": invoice.id}, invoice, None)
-                .await?;
-        }
-        make_invoice(&db, organization, from_date, to_date).await?;
-    }
-    Ok(HttpResponse::Ok().json(organizations))
-}
-
-async fn make_invoice(
-    db: &Database,
-    organization: Organization,
-    from_date: DateTime,
-    to_date: DateTime,
-) -> Result<()> {
-    let tax_ratio = 18;
-    if from_date > to_date {
-        let organization_usage = calculate_usage(from_date, to_date, organization.pricing).await;
+pub async fn generate_invoice(
+    client: web::Data<Client>,
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    numberings: web::Data<Numberings>,
+    leases: web::Data<LeaseConfig>,
+) -> Result<HttpResponse> {
+    let to_date = billing_cutoff(DateTime::now());
+    let run = invoicing::run(&client, &db, &supplier, &numberings, &leases, to_date).await?;
+    Ok(HttpResponse::Ok().json(run))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BillingRunProgress {
+    #[serde(flatten)]
+    run: BillingRun,
+    processed: usize,
+    invoiced_count: usize,
+    skipped_count: usize,
+    failed_count: usize,
+}
+
+#[post("/billing-runs")]
+pub async fn start_billing_run(
+    client: web::Data<Client>,
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    numberings: web::Data<Numberings>,
+    leases: web::Data<LeaseConfig>,
+) -> Result<HttpResponse> {
+    if leases.is_held(&db, BILLING_LEASE).await? {
+        return Err(Error::with_code(
+            "A billing run is already in progress",
+            LEASE_HELD,
+            ErrorKind::LogicalError,
+        ));
     }
-    Ok(())
+    let to_date = billing_cutoff(DateTime::now());
+    let run = invoicing::queue(&db, to_date).await?;
+    let id = run.id;
+    actix_web::rt::spawn(async move {
+        // The run records its outcome, including failures, itself.
+        let _ = invoicing::execute_leased(&client, &db, &supplier, &numberings, &leases, id).await;
+    });
+    Ok(HttpResponse::Accepted().json(run))
+}
+
+#[get("/billing-runs/{id}")]
+pub async fn billing_run_progress(
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let id = ObjectId::parse_str(path.into_inner())?;
+    let run = invoicing::find_run(&db, id).await?;
+    Ok(HttpResponse::Ok().json(BillingRunProgress {
+        processed: run.processed(),
+        invoiced_count: run.invoiced.len(),
+        skipped_count: run.skipped.len(),
+        failed_count: run.failed.len(),
+        run,
+    }))
 }
 
-async fn calculate_usage(
-    from_date: DateTime,
-    to_date: DateTime,
-    pricing: OrganizationPricingTier,
-) -> Vec<OrganizationUsage> {
-    let start_date = from_date;
-    let end_date = to_date;
-    while (end_date > start_date)
-        || (start_date.to_chrono().format("%m").to_string()
-            == end_date.to_chrono().format("%m").to_string())
+#[post("/billing-runs/{id}/cancel")]
+pub async fn cancel_billing_run(
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let id = ObjectId::parse_str(path.into_inner())?;
+    let run = invoicing::cancel(&db, id).await?;
+    Ok(HttpResponse::Ok().json(run))
+}
+
+async fn find_invoice(db: &Database, id: String) -> Result<Invoice> {
+    let id = ObjectId::parse_str(id)?;
+    Invoice::collection(db)
+        .find_one(doc! {"_id": id}, None)
+        .await?
+        .ok_or_else(|| Error::new("Invoice not found", ErrorKind::NotFound))
+}
+
+async fn find_invoice_organization(db: &Database, invoice: &Invoice) -> Result<Organization> {
+    Organization::collection(db)
+        .find_one(doc! {"name": &invoice.organization}, None)
+        .await?
+        .ok_or_else(|| Error::new("Organization not found", ErrorKind::NotFound))
+}
+
+#[get("/invoices")]
+pub async fn list_invoices(
+    db: web::Data<Database>,
+    query: web::Query<InvoiceQuery>,
+) -> Result<HttpResponse> {
+    let page = listing::list_invoices(&db, &query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[get("/invoices/{id}/pdf")]
+pub async fn invoice_pdf(
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let invoice = find_invoice(&db, path.into_inner()).await?;
+    let organization = find_invoice_organization(&db, &invoice).await?;
+    let bytes = pdf::render_invoice(&invoice, &organization, &supplier)?;
+    let file_name = invoice
+        .invoice_no
+        .as_deref()
+        .unwrap_or("draft")
+        .replace('/', "-");
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .insert_header((
+            "Content-Disposition",
+            format!("inline; filename=\"invoice-{}.pdf\"", file_name),
+        ))
+        .body(bytes))
+}
+
+#[get("/invoices/{id}/e-invoice")]
+pub async fn export_e_invoice(
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let invoice = find_invoice(&db, path.into_inner()).await?;
+    let organization = find_invoice_organization(&db, &invoice).await?;
+    let payload = einvoice::build(&invoice, &organization, &supplier)?;
+    Ok(HttpResponse::Ok().json(payload))
+}
+
+#[post("/invoices/{id}/e-invoice")]
+pub async fn register_e_invoice(
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    irp: web::Data<dyn IrpClient>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let invoice = find_invoice(&db, path.into_inner()).await?;
+    if invoice
+        .e_invoice
+        .as_ref()
+        .is_some_and(|registration| !einvoice::is_stub(registration))
     {
-        let total_days = from_date.to_chrono();
+        return Err(Error::new(
+            "Invoice already has an IRN",
+            ErrorKind::LogicalError,
+        ));
     }
-    vec![OrganizationUsage {
-        billing_period: "hello".to_string(),
-        plan: OrganizationPricingTier::Free.to_string(),
-        base_charge: 999.0,
-        additional_usage_charges: 555.0,
-    }]
+    let organization = find_invoice_organization(&db, &invoice).await?;
+    let payload = einvoice::build(&invoice, &organization, &supplier)?;
+    let registration = irp.generate_irn(&payload).await?;
+    Invoice::collection(&db)
+        .update_one(
+            doc! {"_id": invoice.id},
+            doc! {"$set": {
+                "eInvoice": to_bson(&registration)?,
+                "updatedAt": DateTime::now(),
+            }},
+            None,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(registration))
+}
+
+#[post("/invoices/{id}/payments")]
+pub async fn record_payment(
+    client: web::Data<Client>,
+    db: web::Data<Database>,
+    api_keys: web::Data<ApiKeys>,
+    http_request: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<PaymentRequest>,
+) -> Result<HttpResponse> {
+    api_keys.authorize(&http_request)?;
+    let invoice = find_invoice(&db, path.into_inner()).await?;
+    let receipt = payment::record_payment(&client, &db, &invoice, request.into_inner()).await?;
+    Ok(HttpResponse::Created().json(receipt))
+}
+
+#[post("/invoices/{id}/credit-notes")]
+pub async fn issue_credit_note(
+    client: web::Data<Client>,
+    db: web::Data<Database>,
+    numberings: web::Data<Numberings>,
+    api_keys: web::Data<ApiKeys>,
+    http_request: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<CreditNoteRequest>,
+) -> Result<HttpResponse> {
+    api_keys.authorize(&http_request)?;
+    let invoice = find_invoice(&db, path.into_inner()).await?;
+    let credit_note = credit_note::issue_credit_note(
+        &client,
+        &db,
+        &numberings.credit_note,
+        &invoice,
+        request.into_inner(),
+    )
+    .await?;
+    Ok(HttpResponse::Created().json(credit_note))
+}
+
+async fn find_organization(db: &Database, id: String) -> Result<Organization> {
+    let id = ObjectId::parse_str(id)?;
+    Organization::collection(db)
+        .find_one(doc! {"_id": id}, None)
+        .await?
+        .ok_or_else(|| Error::new("Organization not found", ErrorKind::NotFound))
+}
+
+#[get("/organizations/{id}/wallet")]
+pub async fn wallet_statement(
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let organization = find_organization(&db, path.into_inner()).await?;
+    let statement = wallet::statement(&db, &organization).await?;
+    Ok(HttpResponse::Ok().json(statement))
+}
+
+#[get("/organizations/{id}/billing")]
+pub async fn billing_summary(
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let organization = find_organization(&db, path.into_inner()).await?;
+    let summary = summary::billing_summary(&db, &supplier, &organization).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[get("/organizations/{id}/invoice-preview")]
+pub async fn invoice_preview(
+    db: web::Data<Database>,
+    supplier: web::Data<Supplier>,
+    path: web::Path<String>,
+    query: web::Query<PreviewQuery>,
+) -> Result<HttpResponse> {
+    let organization = find_organization(&db, path.into_inner()).await?;
+    let preview = invoicing::preview(&db, &supplier, &organization, &query).await?;
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+#[get("/organizations/{id}/entitlements")]
+pub async fn organization_entitlements(
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let organization = find_organization(&db, path.into_inner()).await?;
+    let now = DateTime::now();
+    let plan = PricingCatalog::load(&db)
+        .await?
+        .resolve(organization.pricing, now);
+    Ok(HttpResponse::Ok().json(entitlement::entitlements(&organization, &plan, now)))
+}
+
+#[post("/organizations/{id}/wallet/top-ups")]
+pub async fn top_up_wallet(
+    client: web::Data<Client>,
+    db: web::Data<Database>,
+    api_keys: web::Data<ApiKeys>,
+    http_request: HttpRequest,
+    path: web::Path<String>,
+    request: web::Json<TopUpRequest>,
+) -> Result<HttpResponse> {
+    api_keys.authorize(&http_request)?;
+    let organization = find_organization(&db, path.into_inner()).await?;
+    let entry = wallet::top_up(&client, &db, &organization, request.into_inner()).await?;
+    Ok(HttpResponse::Created().json(entry))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CatalogQuery {
+    tier: Option<OrganizationPricingTier>,
+}
+
+#[get("/pricing-catalog")]
+pub async fn pricing_catalog(
+    db: web::Data<Database>,
+    query: web::Query<CatalogQuery>,
+) -> Result<HttpResponse> {
+    let catalog = PricingCatalog::load(&db).await?;
+    let versions = catalog
+        .versions()
+        .iter()
+        .filter(|version| query.tier.is_none_or(|tier| version.tier == tier))
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+#[post("/pricing-catalog")]
+pub async fn publish_pricing(
+    db: web::Data<Database>,
+    api_keys: web::Data<ApiKeys>,
+    http_request: HttpRequest,
+    request: web::Json<PricingVersionRequest>,
+) -> Result<HttpResponse> {
+    api_keys.authorize(&http_request)?;
+    let version = catalog::publish(&db, request.into_inner()).await?;
+    Ok(HttpResponse::Created().json(version))
+}
+
+#[post("/usage-events")]
+pub async fn ingest_usage_events(
+    db: web::Data<Database>,
+    api_keys: web::Data<ApiKeys>,
+    request: HttpRequest,
+    batch: web::Json<UsageEventBatch>,
+) -> Result<HttpResponse> {
+    api_keys.authorize(&request)?;
+    let report = metering::ingest(&db, batch.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[post("/dunning/run")]
+pub async fn run_dunning(
+    db: web::Data<Database>,
+    config: web::Data<DunningConfig>,
+    notifier: web::Data<dyn Notifier>,
+) -> Result<HttpResponse> {
+    let report = dunning::run(&db, &config, notifier.get_ref()).await?;
+    Ok(HttpResponse::Ok().json(report))
 }
 
 #[actix_web::main]
@@ -97,10 +391,65 @@ async fn main() -> std::io::Result<()> {
 
     let client = Client::with_options(client_options).unwrap();
     let db = client.default_database().expect("Default database not set");
-    let _server = HttpServer::new(move || {
+    model::create_indexes(&db)
+        .await
+        .expect("Failed to create database indexes");
+    let converted = migration::convert_legacy_amounts(&db)
+        .await
+        .expect("Failed to convert legacy invoice amounts");
+    if converted > 0 {
+        println!(
+            "Converted the amounts of {} legacy invoices to paise",
+            converted
+        );
+    }
+    let supplier = Supplier::from_env();
+    let numberings = Numberings::from_env();
+    let irp = einvoice::client_from_env();
+    let dunning_config = DunningConfig::from_env();
+    let notifier = dunning::notifier_from_env();
+    let leases = LeaseConfig::from_env();
+    let api_keys = ApiKeys::from_env();
+    if let Some(schedule) = Schedule::from_env() {
+        actix_web::rt::spawn(scheduler::run_billing(
+            client.clone(),
+            db.clone(),
+            supplier.clone(),
+            numberings.clone(),
+            leases.clone(),
+            schedule,
+        ));
+    }
+    HttpServer::new(move || {
         App::new()
+            .app_data(web::Data::new(client.clone()))
             .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(supplier.clone()))
+            .app_data(web::Data::new(numberings.clone()))
+            .app_data(web::Data::new(leases.clone()))
+            .app_data(web::Data::new(api_keys.clone()))
+            .app_data(web::Data::from(irp.clone()))
+            .app_data(web::Data::new(dunning_config.clone()))
+            .app_data(web::Data::from(notifier.clone()))
             .service(generate_invoice)
+            .service(start_billing_run)
+            .service(billing_run_progress)
+            .service(cancel_billing_run)
+            .service(list_invoices)
+            .service(invoice_pdf)
+            .service(export_e_invoice)
+            .service(register_e_invoice)
+            .service(record_payment)
+            .service(issue_credit_note)
+            .service(billing_summary)
+            .service(invoice_preview)
+            .service(organization_entitlements)
+            .service(wallet_statement)
+            .service(top_up_wallet)
+            .service(pricing_catalog)
+            .service(publish_pricing)
+            .service(ingest_usage_events)
+            .service(run_dunning)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
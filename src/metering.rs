@@ -0,0 +1,336 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, from_bson, oid::ObjectId, Bson, DateTime, Document},
+    options::UpdateOptions,
+    ClientSession, Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::billing::local_date;
+use crate::error::{is_duplicate_key, Error, ErrorKind, Result};
+use crate::model::{Organization, OrganizationPricing, UsageEvent, UsageMetric, UsageRecord};
+use crate::money::Money;
+
+/// Key of the calendar month containing `date`, e.g. `2026-10`.
+pub fn month_key(date: NaiveDate) -> String {
+    date.format("%Y-%m").to_string()
+}
+
+fn months_filter(organization: &str, from_date: DateTime, to_date: DateTime) -> Document {
+    doc! {
+        "organization": organization,
+        "month": {
+            "$gte": month_key(local_date(from_date)),
+            "$lte": month_key(local_date(to_date)),
+        },
+    }
+}
+
+/// Metered usage of `organization` in the months overlapping `[from_date, to_date)`.
+pub async fn usage_records(
+    db: &Database,
+    organization: &str,
+    from_date: DateTime,
+    to_date: DateTime,
+) -> Result<Vec<UsageRecord>> {
+    Ok(UsageRecord::collection(db)
+        .find(months_filter(organization, from_date, to_date), None)
+        .await?
+        .try_collect::<Vec<UsageRecord>>()
+        .await?)
+}
+
+/// Same as [`usage_records`], read inside the transaction of `session`.
+pub async fn usage_records_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    organization: &str,
+    from_date: DateTime,
+    to_date: DateTime,
+) -> Result<Vec<UsageRecord>> {
+    Ok(UsageRecord::collection(db)
+        .find_with_session(
+            months_filter(organization, from_date, to_date),
+            None,
+            session,
+        )
+        .await?
+        .stream(session)
+        .try_collect::<Vec<UsageRecord>>()
+        .await?)
+}
+
+/// Largest number of events accepted in one batch.
+pub const MAX_BATCH: usize = 500;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEventRequest {
+    /// Unique per event; a repeated id is reported as a duplicate.
+    pub event_id: String,
+    pub organization_id: String,
+    pub metric: UsageMetric,
+    pub quantity: i64,
+    /// When the usage happened, in RFC 3339.
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEventBatch {
+    pub events: Vec<UsageEventRequest>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedEvent {
+    pub event_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestReport {
+    pub accepted: Vec<String>,
+    pub duplicates: Vec<String>,
+    pub rejected: Vec<RejectedEvent>,
+}
+
+async fn organization_name(
+    db: &Database,
+    names: &mut HashMap<String, Option<String>>,
+    id: &str,
+) -> Result<Option<String>> {
+    if let Some(name) = names.get(id) {
+        return Ok(name.clone());
+    }
+    let name = match ObjectId::parse_str(id) {
+        Ok(oid) => Organization::collection(db)
+            .find_one(doc! {"_id": oid}, None)
+            .await?
+            .map(|organization| organization.name),
+        Err(_) => None,
+    };
+    names.insert(id.to_string(), name.clone());
+    Ok(name)
+}
+
+/// Stores a batch of usage events and refreshes the monthly usage they fall in.
+/// Events already received are reported as duplicates and not counted again.
+pub async fn ingest(db: &Database, batch: UsageEventBatch) -> Result<IngestReport> {
+    if batch.events.len() > MAX_BATCH {
+        return Err(Error::new(
+            format!("A batch holds at most {} events", MAX_BATCH),
+            ErrorKind::InvalidData,
+        ));
+    }
+    let mut report = IngestReport::default();
+    let mut names = HashMap::new();
+    let mut months = BTreeSet::new();
+    for request in batch.events {
+        let reject = |reason: &str| RejectedEvent {
+            event_id: request.event_id.clone(),
+            reason: reason.to_string(),
+        };
+        if request.event_id.trim().is_empty() {
+            report.rejected.push(reject("eventId is required"));
+            continue;
+        }
+        if request.quantity < 0 {
+            report.rejected.push(reject("quantity cannot be negative"));
+            continue;
+        }
+        let timestamp = match DateTime::parse_rfc3339_str(&request.timestamp) {
+            Ok(timestamp) => timestamp,
+            Err(_) => {
+                report
+                    .rejected
+                    .push(reject("timestamp must be an RFC 3339 date-time"));
+                continue;
+            }
+        };
+        let organization = match organization_name(db, &mut names, &request.organization_id).await?
+        {
+            Some(organization) => organization,
+            None => {
+                report.rejected.push(reject("Organization not found"));
+                continue;
+            }
+        };
+        let event = UsageEvent {
+            id: request.event_id,
+            organization,
+            metric: request.metric,
+            quantity: request.quantity,
+            timestamp,
+            month: month_key(local_date(timestamp)),
+            received_at: DateTime::now(),
+        };
+        // A duplicate still refreshes its month, so resending a batch whose
+        // aggregation failed repairs the totals.
+        months.insert((event.organization.clone(), event.month.clone()));
+        match UsageEvent::collection(db).insert_one(&event, None).await {
+            Ok(_) => report.accepted.push(event.id),
+            Err(err) if is_duplicate_key(&err) => report.duplicates.push(event.id),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    for (organization, month) in months {
+        aggregate(db, &organization, &month).await?;
+    }
+    Ok(report)
+}
+
+/// Recomputes the usage of `organization` in `month` from its events.
+pub async fn aggregate(db: &Database, organization: &str, month: &str) -> Result<()> {
+    let pipeline = vec![
+        doc! {"$match": {"organization": organization, "month": month}},
+        doc! {"$group": {
+            "_id": "$metric",
+            "total": {"$sum": "$quantity"},
+            "peak": {"$max": "$quantity"},
+        }},
+    ];
+    let groups = UsageEvent::collection(db)
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?;
+    let mut usage = doc! {
+        "vouchers": 0_i64,
+        "storage": 0_i64,
+        "users": 0_i64,
+        "branches": 0_i64,
+        "updatedAt": DateTime::now(),
+    };
+    for group in groups {
+        let metric: UsageMetric = from_bson(group.get("_id").cloned().unwrap_or(Bson::Null))?;
+        let value = |field: &str| group.get(field).and_then(Bson::as_i64).unwrap_or(0);
+        match metric {
+            UsageMetric::Vouchers => usage.insert("vouchers", value("total")),
+            UsageMetric::Storage => usage.insert("storage", value("peak")),
+            UsageMetric::Users => usage.insert("users", value("peak")),
+            UsageMetric::Branches => usage.insert("branches", value("peak")),
+        };
+    }
+    let filter = doc! {"organization": organization, "month": month};
+    let update = doc! {"$set": usage};
+    let mut attempt = 1;
+    loop {
+        let written = UsageRecord::collection(db)
+            .update_one(
+                filter.clone(),
+                update.clone(),
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await;
+        match written {
+            Ok(_) => return Ok(()),
+            // Two first aggregations for the month raced; the second now updates the record.
+            Err(err) if is_duplicate_key(&err) && attempt == 1 => attempt += 1,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+fn blocks(used: usize, allowance: usize, block: usize) -> usize {
+    match block {
+        0 => 0,
+        block => used.saturating_sub(allowance).div_ceil(block),
+    }
+}
+
+/// Charge for `record`'s consumption beyond the allowances of `plan`, with a
+/// sentence explaining it; `None` when the month stayed within the allowances.
+pub fn overage(plan: &OrganizationPricing, record: &UsageRecord) -> Option<(Money, String)> {
+    let rates = plan.overage;
+    let mut charge = Money::ZERO;
+    let mut explanations = Vec::new();
+    let voucher_blocks = blocks(record.vouchers, plan.vouchers, rates.voucher_block);
+    if voucher_blocks > 0 {
+        charge += Money::from_rupees((voucher_blocks * rates.voucher_block_price) as i64);
+        explanations.push(format!(
+            "{} vouchers against an allowance of {}: {} x {} vouchers at {}",
+            record.vouchers,
+            plan.vouchers,
+            voucher_blocks,
+            rates.voucher_block,
+            Money::from_rupees(rates.voucher_block_price as i64)
+        ));
+    }
+    let storage_blocks = blocks(record.storage, plan.storage, rates.storage_block);
+    if storage_blocks > 0 {
+        charge += Money::from_rupees((storage_blocks * rates.storage_block_price) as i64);
+        explanations.push(format!(
+            "{} MB stored against an allowance of {} MB: {} x {} MB at {}",
+            record.storage,
+            plan.storage,
+            storage_blocks,
+            rates.storage_block,
+            Money::from_rupees(rates.storage_block_price as i64)
+        ));
+    }
+    if explanations.is_empty() {
+        None
+    } else {
+        Some((charge, explanations.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(vouchers: usize, storage: usize) -> UsageRecord {
+        UsageRecord {
+            id: ObjectId::new(),
+            organization: String::from("acme"),
+            month: String::from("2026-04"),
+            vouchers,
+            storage,
+            users: 0,
+            branches: 0,
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn usage_within_the_allowances_is_not_charged() {
+        let plan = OrganizationPricing::t5();
+        assert!(overage(&plan, &record(plan.vouchers, plan.storage)).is_none());
+        assert!(overage(&plan, &record(0, 0)).is_none());
+    }
+
+    #[test]
+    fn a_part_block_is_charged_as_a_whole_one() {
+        let plan = OrganizationPricing::t5();
+        let charge = |vouchers, storage| overage(&plan, &record(vouchers, storage)).unwrap().0;
+        assert_eq!(charge(plan.vouchers + 1, 0), Money::from_rupees(99));
+        assert_eq!(charge(plan.vouchers + 1000, 0), Money::from_rupees(198));
+        assert_eq!(charge(plan.vouchers + 1001, 0), Money::from_rupees(297));
+        assert_eq!(charge(0, plan.storage + 1025), Money::from_rupees(298));
+    }
+
+    #[test]
+    fn voucher_and_storage_overage_add_up() {
+        let plan = OrganizationPricing::t5();
+        let (charge, explanation) =
+            overage(&plan, &record(plan.vouchers + 1, plan.storage + 1)).unwrap();
+        assert_eq!(charge, Money::from_rupees(99 + 149));
+        assert_eq!(
+            explanation,
+            "80001 vouchers against an allowance of 80000: 1 x 500 vouchers at 99.00; \
+             4001 MB stored against an allowance of 4000 MB: 1 x 1024 MB at 149.00"
+        );
+    }
+
+    #[test]
+    fn a_zero_block_size_charges_nothing() {
+        let mut plan = OrganizationPricing::t5();
+        plan.overage.voucher_block = 0;
+        assert!(overage(&plan, &record(plan.vouchers * 2, 0)).is_none());
+    }
+}
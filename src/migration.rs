@@ -0,0 +1,74 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, from_document, to_bson, Document},
+    Database,
+};
+
+use crate::error::Result;
+use crate::model::Invoice;
+
+/// Invoice amounts that documents written before amounts were exact hold as
+/// rupee doubles.
+const LEGACY_AMOUNTS: [&str; 9] = [
+    "serviceValue",
+    "cgstValue",
+    "sgstValue",
+    "igstValue",
+    "taxValue",
+    "totalValue",
+    "roundedValue",
+    "organizationUsage.baseCharge",
+    "organizationUsage.additionalUsageCharges",
+];
+
+/// Rewrites legacy rupee amounts on invoices as integer paise, so queries that
+/// filter or sort on an amount compare like with like. Safe to run on every
+/// start; invoices already converted are not matched. An invoice that cannot be
+/// read is logged and left as it is.
+pub async fn convert_legacy_amounts(db: &Database) -> Result<u64> {
+    let legacy = LEGACY_AMOUNTS
+        .iter()
+        .map(|field| doc! {*field: {"$type": "double"}})
+        .collect::<Vec<Document>>();
+    // Read raw documents so one malformed invoice is skipped alone.
+    let documents = Invoice::collection(db)
+        .clone_with_type::<Document>()
+        .find(doc! {"$or": legacy}, None)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?;
+    let mut converted = 0;
+    for document in documents {
+        let invoice = match from_document::<Invoice>(document.clone()) {
+            Ok(invoice) => invoice,
+            Err(err) => {
+                let id = document
+                    .get_object_id("_id")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_else(|_| String::from("-"));
+                println!("Legacy invoice {} left unconverted: {}", id, err);
+                continue;
+            }
+        };
+        // Reading the invoice already converted its amounts; write them back as
+        // they are now, unless the invoice changed since it was read.
+        let result = Invoice::collection(db)
+            .update_one(
+                doc! {"_id": invoice.id, "updatedAt": invoice.updated_at},
+                doc! {"$set": {
+                    "serviceValue": invoice.service_value,
+                    "cgstValue": invoice.cgst_value,
+                    "sgstValue": invoice.sgst_value,
+                    "igstValue": invoice.igst_value,
+                    "taxValue": invoice.tax_value,
+                    "totalValue": invoice.total_value,
+                    "roundedValue": invoice.rounded_value,
+                    "organizationUsage": to_bson(&invoice.organization_usage)?,
+                }},
+                None,
+            )
+            .await?;
+        converted += result.modified_count;
+    }
+    Ok(converted)
+}
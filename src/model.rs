@@ -1,12 +1,15 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use mongodb::{
     bson::{doc, oid::ObjectId, to_bson, Bson, DateTime},
-    Collection, Database,
+    options::IndexOptions,
+    Collection, Database, IndexModel,
 };
 use serde::{de, Deserialize, Serialize, Serializer};
 use strum::{Display, EnumString};
 
+use crate::money::{deserialize_stored, Money};
+
 #[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum OrganizationPricingTier {
@@ -24,20 +27,23 @@ impl From<OrganizationPricingTier> for Bson {
     }
 }
 
-impl ToString for OrganizationPricingTier {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Free => String::from("FREE"),
-            Self::T1 => String::from("T1"),
-            Self::T2 => String::from("T2"),
-            Self::T3 => String::from("T3"),
-            Self::T4 => String::from("T4"),
-            Self::T5 => String::from("T5"),
-        }
+impl fmt::Display for OrganizationPricingTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Self::Free => "FREE",
+            Self::T1 => "T1",
+            Self::T2 => "T2",
+            Self::T3 => "T3",
+            Self::T4 => "T4",
+            Self::T5 => "T5",
+        };
+        f.write_str(value)
     }
 }
 
 impl OrganizationPricingTier {
+    /// Built-in plan definition, in effect until the pricing catalog has a version
+    /// for this tier.
     pub fn info(&self) -> OrganizationPricing {
         match self {
             Self::Free => OrganizationPricing::free(),
@@ -105,7 +111,7 @@ impl<'de> Deserialize<'de> for OrganizationFeatures {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrganizationPricing {
     pub tier: OrganizationPricingTier,
@@ -120,6 +126,10 @@ pub struct OrganizationPricing {
     pub storage: usize,
     pub clients: usize,
     pub warehouse: usize,
+    pub addition_pricing: OrganizationAdditionPricing,
+    /// Charges for metered use beyond `vouchers` and `storage`.
+    #[serde(default)]
+    pub overage: OverageRates,
 }
 
 impl OrganizationPricing {
@@ -137,6 +147,14 @@ impl OrganizationPricing {
             storage: 500,
             clients: 0,
             warehouse: 0,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 0,
+                user: 0,
+                cash_register: 0,
+                client: 0,
+                warehouse: 0,
+            },
+            overage: OverageRates::default(),
         }
     }
 
@@ -158,6 +176,14 @@ impl OrganizationPricing {
             storage: 500,
             clients: 1,
             warehouse: 0,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 399,
+                user: 99,
+                cash_register: 199,
+                client: 49,
+                warehouse: 299,
+            },
+            overage: OverageRates::default(),
         }
     }
 
@@ -179,6 +205,14 @@ impl OrganizationPricing {
             storage: 500,
             clients: 5,
             warehouse: 0,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 699,
+                user: 89,
+                cash_register: 199,
+                client: 49,
+                warehouse: 299,
+            },
+            overage: OverageRates::default(),
         }
     }
 
@@ -206,6 +240,14 @@ impl OrganizationPricing {
             storage: 1000,
             clients: 12,
             warehouse: 0,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 999,
+                user: 79,
+                cash_register: 149,
+                client: 39,
+                warehouse: 499,
+            },
+            overage: OverageRates::default(),
         }
     }
 
@@ -231,6 +273,14 @@ impl OrganizationPricing {
             storage: 2000,
             clients: 30,
             warehouse: 1,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 1499,
+                user: 69,
+                cash_register: 129,
+                client: 29,
+                warehouse: 699,
+            },
+            overage: OverageRates::default(),
         }
     }
 
@@ -255,10 +305,110 @@ impl OrganizationPricing {
             storage: 4000,
             clients: 70,
             warehouse: 2,
+            addition_pricing: OrganizationAdditionPricing {
+                branch: 1999,
+                user: 59,
+                cash_register: 99,
+                client: 19,
+                warehouse: 899,
+            },
+            overage: OverageRates::default(),
+        }
+    }
+
+    /// The part of `additions` this plan lets an organization use: users and
+    /// branches beyond `max_users` and `max_branches` are neither usable nor billed.
+    pub fn usable_additions(
+        &self,
+        additions: OrganizationPricingAdditions,
+    ) -> OrganizationPricingAdditions {
+        let allowed = |max: Option<usize>, included: usize, added: usize| match max {
+            Some(max) => added.min(max.saturating_sub(included)),
+            None => added,
+        };
+        OrganizationPricingAdditions {
+            users: allowed(self.max_users, self.users, additions.users),
+            branches: allowed(self.max_branches, self.branches, additions.branches),
+            ..additions
         }
     }
 }
 
+/// Price of each block of vouchers or storage used in a month beyond the tier
+/// allowance; a part block is charged as a whole one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverageRates {
+    pub voucher_block: usize,
+    pub voucher_block_price: usize,
+    /// Block size in MB.
+    pub storage_block: usize,
+    pub storage_block_price: usize,
+}
+
+impl Default for OverageRates {
+    fn default() -> Self {
+        OverageRates {
+            voucher_block: 500,
+            voucher_block_price: 99,
+            storage_block: 1024,
+            storage_block_price: 149,
+        }
+    }
+}
+
+/// Monthly price of one unit of each addition bought beyond the tier limits.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationAdditionPricing {
+    pub branch: usize,
+    pub user: usize,
+    pub cash_register: usize,
+    pub client: usize,
+    pub warehouse: usize,
+}
+
+impl OrganizationAdditionPricing {
+    pub fn unit_price(&self, addition: OrganizationAddition) -> usize {
+        match addition {
+            OrganizationAddition::Branches => self.branch,
+            OrganizationAddition::Users => self.user,
+            OrganizationAddition::CashRegisters => self.cash_register,
+            OrganizationAddition::Clients => self.client,
+            OrganizationAddition::Warehouse => self.warehouse,
+        }
+    }
+}
+
+#[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrganizationAddition {
+    Branches,
+    Users,
+    CashRegisters,
+    Clients,
+    Warehouse,
+}
+
+impl From<OrganizationAddition> for Bson {
+    fn from(value: OrganizationAddition) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+impl fmt::Display for OrganizationAddition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Self::Branches => "BRANCHES",
+            Self::Users => "USERS",
+            Self::CashRegisters => "CASH_REGISTERS",
+            Self::Clients => "CLIENTS",
+            Self::Warehouse => "WAREHOUSE",
+        };
+        f.write_str(value)
+    }
+}
+
 #[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum OrganizationStatus {
@@ -273,13 +423,14 @@ impl From<OrganizationStatus> for Bson {
     }
 }
 
-impl ToString for OrganizationStatus {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Active => String::from("ACTIVE"),
-            Self::Suspended => String::from("SUSPENDED"),
-            Self::Deactivated => String::from("DEACTIVATED"),
-        }
+impl fmt::Display for OrganizationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Self::Active => "ACTIVE",
+            Self::Suspended => "SUSPENDED",
+            Self::Deactivated => "DEACTIVATED",
+        };
+        f.write_str(value)
     }
 }
 
@@ -291,16 +442,35 @@ pub struct OrganizationPricingAdditions {
     pub cash_registers: usize,
     pub clients: usize,
     pub warehouse: usize,
+    /// When these addition counts took effect; billing prorates from here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_from: Option<DateTime>,
+}
+
+impl OrganizationPricingAdditions {
+    pub fn quantities(&self) -> [(OrganizationAddition, usize); 5] {
+        [
+            (OrganizationAddition::Branches, self.branches),
+            (OrganizationAddition::Users, self.users),
+            (OrganizationAddition::CashRegisters, self.cash_registers),
+            (OrganizationAddition::Clients, self.clients),
+            (OrganizationAddition::Warehouse, self.warehouse),
+        ]
+    }
 }
+
 impl From<OrganizationPricingAdditions> for Bson {
     fn from(additions: OrganizationPricingAdditions) -> Self {
-        let additions_doc = doc! {
+        let mut additions_doc = doc! {
             "branches": additions.branches as u32,
             "users": additions.users as u32,
             "cashRegisters": additions.cash_registers as u32,
             "clients": additions.clients as u32,
             "warehouse": additions.warehouse as u32,
         };
+        if let Some(effective_from) = additions.effective_from {
+            additions_doc.insert("effectiveFrom", effective_from);
+        }
         Bson::Document(additions_doc)
     }
 }
@@ -358,9 +528,17 @@ pub struct Organization {
     pub unbilled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub additions: Option<OrganizationPricingAdditions>,
+    /// Addition counts held before `additions`, each from its own `effective_from`,
+    /// so that a change mid-period bills the earlier counts up to the change.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addition_history: Vec<OrganizationPricingAdditions>,
     pub status: OrganizationStatus,
+    /// Legacy prepaid fund in rupees; moved into the wallet ledger on first use.
     #[serde(default)]
     pub fund: usize,
+    /// Balance of the wallet ledger, kept in step with every `WalletEntry`.
+    #[serde(default)]
+    pub wallet_balance: Money,
     pub owned_by: ObjectId,
     pub created_at: DateTime,
     pub updated_at: DateTime,
@@ -370,6 +548,18 @@ impl Organization {
     pub fn collection(db: &Database) -> Collection<Self> {
         db.collection("organizations")
     }
+
+    /// Every set of addition counts the organization has held, oldest first.
+    pub fn addition_timeline(&self) -> Vec<OrganizationPricingAdditions> {
+        let mut timeline = self
+            .addition_history
+            .iter()
+            .copied()
+            .chain(self.additions)
+            .collect::<Vec<_>>();
+        timeline.sort_by_key(|additions| additions.effective_from);
+        timeline
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -377,8 +567,41 @@ impl Organization {
 pub struct OrganizationUsage {
     pub billing_period: String,
     pub plan: String,
-    pub base_charge: f32,
-    pub additional_usage_charges: f32,
+    /// Set on lines that bill additions rather than the tier itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addition: Option<OrganizationAdditionUsage>,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub base_charge: Money,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub additional_usage_charges: Money,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationAdditionUsage {
+    pub kind: OrganizationAddition,
+    pub quantity: usize,
+    pub unit_price: usize,
+    pub days: u32,
+}
+
+impl OrganizationUsage {
+    pub fn description(&self) -> String {
+        match &self.addition {
+            Some(addition) => format!(
+                "{} plan addition: {} x {} @ {}/month",
+                self.plan, addition.quantity, addition.kind, addition.unit_price
+            ),
+            None if self.additional_usage_charges.is_positive() => {
+                format!("{} plan subscription with metered overage", self.plan)
+            }
+            None => format!("{} plan subscription", self.plan),
+        }
+    }
+
+    pub fn amount(&self) -> Money {
+        self.base_charge + self.additional_usage_charges
+    }
 }
 
 impl From<OrganizationUsage> for Bson {
@@ -391,6 +614,8 @@ impl From<OrganizationUsage> for Bson {
 #[serde(rename_all = "UPPERCASE")]
 pub enum PaidStatus {
     Paid,
+    #[serde(rename = "PARTIALLY_PAID")]
+    PartiallyPaid,
     Unpaid,
 }
 
@@ -400,12 +625,14 @@ impl From<PaidStatus> for Bson {
     }
 }
 
-impl ToString for PaidStatus {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Paid => String::from("PAID"),
-            Self::Unpaid => String::from("UNPAID"),
-        }
+impl fmt::Display for PaidStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Self::Paid => "PAID",
+            Self::PartiallyPaid => "PARTIALLY_PAID",
+            Self::Unpaid => "UNPAID",
+        };
+        f.write_str(value)
     }
 }
 
@@ -414,18 +641,52 @@ impl ToString for PaidStatus {
 pub struct Invoice {
     #[serde(rename = "_id")]
     pub id: ObjectId,
-    pub invoice_no: u8,
+    /// Allocated from the financial-year sequence when the draft is finalized.
+    #[serde(default, deserialize_with = "deserialize_invoice_no")]
+    pub invoice_no: Option<String>,
+    /// End of the billed period, exclusive.
     pub date: DateTime,
+    /// Start of the billed period, inclusive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_start: Option<DateTime>,
+    /// Billed period as `YYYY-MM-DD - YYYY-MM-DD`; unique per organization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub billing_period: Option<String>,
     pub billed_to: String,
     pub organization: String,
     pub organization_usage: Vec<OrganizationUsage>,
-    pub service_value: f32,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub service_value: Money,
+    /// GST state code of the place of supply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub place_of_supply: Option<String>,
     pub tax_ratio: f32,
-    pub tax_value: f32,
-    pub total_value: f32,
-    pub rounded_value: f32,
+    #[serde(default, deserialize_with = "deserialize_stored")]
+    pub cgst_value: Money,
+    #[serde(default, deserialize_with = "deserialize_stored")]
+    pub sgst_value: Money,
+    #[serde(default, deserialize_with = "deserialize_stored")]
+    pub igst_value: Money,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub tax_value: Money,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub total_value: Money,
+    #[serde(deserialize_with = "deserialize_stored")]
+    pub rounded_value: Money,
     pub draft: bool,
+    /// When the draft was finalized and the invoice became payable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at: Option<DateTime>,
     pub paid_status: PaidStatus,
+    /// Sum of the payments recorded against this invoice.
+    #[serde(default)]
+    pub amount_paid: Money,
+    /// Sum of the credit notes issued against this invoice.
+    #[serde(default)]
+    pub credited_value: Money,
+    /// Registration returned by the invoice registration portal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e_invoice: Option<EInvoiceRegistration>,
     pub created_at: DateTime,
     pub updated_at: DateTime,
 }
@@ -434,4 +695,570 @@ impl Invoice {
     pub fn collection(db: &Database) -> Collection<Self> {
         db.collection("invoices")
     }
+
+    /// Day payment falls due, counted from issue; drafts are never due.
+    pub fn due_date(&self) -> Option<DateTime> {
+        match self.draft {
+            true => None,
+            false => Some(self.issued_at.unwrap_or(self.date)),
+        }
+    }
+
+    /// Amount still due after payments and credit notes, never below zero.
+    pub fn outstanding(&self) -> Money {
+        (self.rounded_value - self.credited_value - self.amount_paid).max(Money::ZERO)
+    }
+
+    pub fn settled_status(&self) -> PaidStatus {
+        if self.outstanding().is_zero() {
+            PaidStatus::Paid
+        } else if self.amount_paid.is_positive() {
+            PaidStatus::PartiallyPaid
+        } else {
+            PaidStatus::Unpaid
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EInvoiceRegistration {
+    pub irn: String,
+    pub ack_no: String,
+    pub ack_date: DateTime,
+    pub signed_qr_code: String,
+}
+
+/// Reads invoice numbers, including the bare integers stored by older documents.
+fn deserialize_invoice_no<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum InvoiceNo {
+        Number(i64),
+        Text(String),
+    }
+    Ok(match Option::<InvoiceNo>::deserialize(deserializer)? {
+        Some(InvoiceNo::Text(invoice_no)) => Some(invoice_no),
+        Some(InvoiceNo::Number(invoice_no)) if invoice_no > 0 => Some(invoice_no.to_string()),
+        _ => None,
+    })
+}
+
+#[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PaymentMode {
+    Upi,
+    Neft,
+    Cheque,
+    Card,
+    Wallet,
+}
+
+impl From<PaymentMode> for Bson {
+    fn from(value: PaymentMode) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Payment {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub invoice: ObjectId,
+    pub organization: String,
+    pub amount: Money,
+    pub mode: PaymentMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub date: DateTime,
+    pub created_at: DateTime,
+}
+
+impl Payment {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("payments")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNoteLine {
+    /// Position of the reversed line in the invoice's `organization_usage`.
+    pub line: usize,
+    pub description: String,
+    pub billing_period: String,
+    pub amount: Money,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNote {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub credit_note_no: String,
+    pub date: DateTime,
+    pub invoice: ObjectId,
+    pub invoice_no: String,
+    pub organization: String,
+    pub reason: String,
+    pub lines: Vec<CreditNoteLine>,
+    pub service_value: Money,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub place_of_supply: Option<String>,
+    pub tax_ratio: f32,
+    pub cgst_value: Money,
+    pub sgst_value: Money,
+    pub igst_value: Money,
+    pub tax_value: Money,
+    pub total_value: Money,
+    pub rounded_value: Money,
+    pub created_at: DateTime,
+}
+
+impl CreditNote {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("credit_notes")
+    }
+}
+
+#[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WalletEntryKind {
+    OpeningBalance,
+    TopUp,
+    InvoiceApplication,
+}
+
+impl From<WalletEntryKind> for Bson {
+    fn from(value: WalletEntryKind) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+/// One movement of an organization's prepaid wallet.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalletEntry {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub organization: String,
+    pub kind: WalletEntryKind,
+    /// Positive for money coming in, negative for money applied.
+    pub amount: Money,
+    /// Wallet balance right after this movement.
+    pub balance: Money,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub created_at: DateTime,
+}
+
+impl WalletEntry {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("wallet_entries")
+    }
+}
+
+#[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DunningEventKind {
+    Reminder,
+    Suspended,
+    Reactivated,
+}
+
+impl From<DunningEventKind> for Bson {
+    fn from(value: DunningEventKind) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+/// A reminder sent or an organization status change made by dunning.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DunningEvent {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub organization: String,
+    pub kind: DunningEventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<ObjectId>,
+    /// Days past due that triggered a reminder.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset_days: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_status: Option<OrganizationStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_status: Option<OrganizationStatus>,
+    pub reason: String,
+    pub created_at: DateTime,
+}
+
+impl DunningEvent {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("dunning_events")
+    }
+}
+
+/// Monotonic counters, keyed by what they number (e.g. `INVOICE/2026-27`).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sequence {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub value: i64,
+}
+
+impl Sequence {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("sequences")
+    }
+}
+
+#[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BillingSkipReason {
+    Unbilled,
+    Free,
+    Suspended,
+    Deactivated,
+    /// The organization is already invoiced through the run's billing date.
+    UpToDate,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoicedOrganization {
+    pub organization: String,
+    pub invoice: ObjectId,
+    pub total_value: Money,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedOrganization {
+    pub organization: String,
+    pub reason: BillingSkipReason,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedOrganization {
+    /// Organization name, or its id when the document could not be read.
+    pub organization: String,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Eq, Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BillingRunStatus {
+    Queued,
+    Running,
+    /// Also the status of runs recorded before statuses were tracked.
+    #[default]
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+impl From<BillingRunStatus> for Bson {
+    fn from(value: BillingRunStatus) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+/// Progress and outcome of one billing run, per organization.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BillingRun {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(default)]
+    pub status: BillingRunStatus,
+    /// Usage is billed up to, but not including, this instant.
+    pub billed_through: DateTime,
+    /// Organizations the run will go through, once it has listed them.
+    #[serde(default)]
+    pub total: usize,
+    pub invoiced: Vec<InvoicedOrganization>,
+    pub skipped: Vec<SkippedOrganization>,
+    pub failed: Vec<FailedOrganization>,
+    /// Why the run as a whole failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Set to stop the run after the organization it is billing.
+    #[serde(default)]
+    pub cancel_requested: bool,
+    pub started_at: DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime>,
+}
+
+impl BillingRun {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("billing_runs")
+    }
+
+    /// Organizations dealt with so far.
+    pub fn processed(&self) -> usize {
+        self.invoiced.len() + self.skipped.len() + self.failed.len()
+    }
+}
+
+/// Last successful run of a job started by the in-process scheduler.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledJob {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub last_run_at: DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billing_run: Option<ObjectId>,
+    pub updated_at: DateTime,
+}
+
+impl ScheduledJob {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("scheduled_jobs")
+    }
+}
+
+/// Metered consumption of an organization in one calendar month, aggregated
+/// from its usage events.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub organization: String,
+    /// Calendar month in IST, as `YYYY-MM`.
+    pub month: String,
+    /// Vouchers created during the month.
+    pub vouchers: usize,
+    /// Peak storage during the month, in MB.
+    pub storage: usize,
+    /// Peak users during the month.
+    #[serde(default)]
+    pub users: usize,
+    /// Peak branches during the month.
+    #[serde(default)]
+    pub branches: usize,
+    pub updated_at: DateTime,
+}
+
+impl UsageRecord {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("usage_records")
+    }
+}
+
+#[derive(Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UsageMetric {
+    /// Vouchers created; events add up over the month.
+    Vouchers,
+    /// Storage in use, in MB; the month keeps the peak.
+    Storage,
+    /// Users in use; the month keeps the peak.
+    Users,
+    /// Branches in use; the month keeps the peak.
+    Branches,
+}
+
+impl From<UsageMetric> for Bson {
+    fn from(value: UsageMetric) -> Self {
+        to_bson(&value).unwrap()
+    }
+}
+
+/// A usage report received from another service, kept so that monthly usage can
+/// be recomputed and repeated reports recognised.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEvent {
+    /// Event id chosen by the sender.
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub organization: String,
+    pub metric: UsageMetric,
+    pub quantity: i64,
+    pub timestamp: DateTime,
+    /// Calendar month of `timestamp` in IST, as `YYYY-MM`.
+    pub month: String,
+    pub received_at: DateTime,
+}
+
+impl UsageEvent {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("usage_events")
+    }
+}
+
+/// One version of a tier's plan in the pricing catalog, in effect from
+/// `effective_from` until a later version of the same tier takes over.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PricingVersion {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub tier: OrganizationPricingTier,
+    pub version: i64,
+    pub effective_from: DateTime,
+    pub plan: OrganizationPricing,
+    pub created_at: DateTime,
+}
+
+impl PricingVersion {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("pricing_catalog")
+    }
+}
+
+/// Exclusive claim on a named job, held by one server instance until it expires.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lease {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub holder: String,
+    pub expires_at: DateTime,
+    pub acquired_at: DateTime,
+    pub renewed_at: DateTime,
+}
+
+impl Lease {
+    pub fn collection(db: &Database) -> Collection<Self> {
+        db.collection("leases")
+    }
+}
+
+pub async fn create_indexes(db: &Database) -> mongodb::error::Result<()> {
+    Invoice::collection(db)
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"organization": 1, "billingPeriod": 1})
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! {"billingPeriod": {"$exists": true}})
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await?;
+    UsageRecord::collection(db)
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"organization": 1, "month": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+    UsageEvent::collection(db)
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"organization": 1, "month": 1})
+                .build(),
+            None,
+        )
+        .await?;
+    // Clears leases left behind by crashed holders; expiry is still checked on acquire.
+    Lease::collection(db)
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {"expiresAt": 1})
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(std::time::Duration::ZERO)
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A finalized invoice for 1180.00.
+    fn invoice() -> Invoice {
+        let now = DateTime::now();
+        Invoice {
+            id: ObjectId::new(),
+            invoice_no: Some(String::from("AP/2026-27/000001")),
+            date: now,
+            period_start: None,
+            billing_period: None,
+            billed_to: String::from("Acme Traders"),
+            organization: String::from("acme"),
+            organization_usage: Vec::new(),
+            service_value: Money::from_rupees(1000),
+            place_of_supply: None,
+            tax_ratio: 18.0,
+            cgst_value: Money::ZERO,
+            sgst_value: Money::ZERO,
+            igst_value: Money::from_rupees(180),
+            tax_value: Money::from_rupees(180),
+            total_value: Money::from_rupees(1180),
+            rounded_value: Money::from_rupees(1180),
+            draft: false,
+            issued_at: Some(now),
+            paid_status: PaidStatus::Unpaid,
+            amount_paid: Money::ZERO,
+            credited_value: Money::ZERO,
+            e_invoice: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn payments_reduce_the_outstanding_balance() {
+        let mut invoice = invoice();
+        assert_eq!(invoice.outstanding(), Money::from_rupees(1180));
+        assert_eq!(invoice.settled_status(), PaidStatus::Unpaid);
+        invoice.amount_paid = Money::from_paise(50_050);
+        assert_eq!(invoice.outstanding(), Money::from_paise(67_950));
+        assert_eq!(invoice.settled_status(), PaidStatus::PartiallyPaid);
+        invoice.amount_paid = Money::from_rupees(1180);
+        assert_eq!(invoice.outstanding(), Money::ZERO);
+        assert_eq!(invoice.settled_status(), PaidStatus::Paid);
+    }
+
+    #[test]
+    fn credit_notes_reduce_the_outstanding_balance() {
+        let mut invoice = invoice();
+        invoice.credited_value = Money::from_rupees(180);
+        assert_eq!(invoice.outstanding(), Money::from_rupees(1000));
+        // A credit is not a payment, so the invoice is still unpaid.
+        assert_eq!(invoice.settled_status(), PaidStatus::Unpaid);
+        invoice.amount_paid = Money::from_rupees(400);
+        assert_eq!(invoice.settled_status(), PaidStatus::PartiallyPaid);
+        invoice.credited_value = Money::from_rupees(780);
+        assert_eq!(invoice.outstanding(), Money::ZERO);
+        assert_eq!(invoice.settled_status(), PaidStatus::Paid);
+        // Fully credited without any payment.
+        invoice.amount_paid = Money::ZERO;
+        invoice.credited_value = Money::from_rupees(1180);
+        assert_eq!(invoice.settled_status(), PaidStatus::Paid);
+    }
+
+    #[test]
+    fn outstanding_never_goes_below_zero() {
+        let mut invoice = invoice();
+        invoice.amount_paid = Money::from_rupees(1200);
+        assert_eq!(invoice.outstanding(), Money::ZERO);
+        assert_eq!(invoice.settled_status(), PaidStatus::Paid);
+    }
 }
@@ -0,0 +1,410 @@
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+};
+
+use mongodb::bson::Bson;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// How a fractional paisa is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Half a unit and above rounds away from zero.
+    HalfUp,
+    /// Half a unit rounds to the even neighbour.
+    HalfEven,
+    /// Always towards zero.
+    Down,
+    /// Always away from zero.
+    Up,
+}
+
+impl Rounding {
+    /// Divides `num` by a positive `den`, resolving the remainder by this mode.
+    fn div(self, num: i128, den: i128) -> i128 {
+        let quotient = num / den;
+        let remainder = num % den;
+        if remainder == 0 {
+            return quotient;
+        }
+        let away = if num < 0 { quotient - 1 } else { quotient + 1 };
+        let twice = remainder.abs() * 2;
+        match self {
+            Rounding::Down => quotient,
+            Rounding::Up => away,
+            Rounding::HalfUp if twice >= den => away,
+            Rounding::HalfUp => quotient,
+            Rounding::HalfEven if twice > den || (twice == den && quotient % 2 != 0) => away,
+            Rounding::HalfEven => quotient,
+        }
+    }
+}
+
+/// An exact amount of Indian rupees, held as a whole number of paise.
+///
+/// Stored in Mongo and sent over JSON as integer paise; a fractional number is
+/// rejected. Documents written before amounts were exact hold rupees as doubles,
+/// which only [`deserialize_stored`] accepts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub fn from_paise(paise: i64) -> Money {
+        Money(paise)
+    }
+
+    pub fn from_rupees(rupees: i64) -> Money {
+        Money(rupees * 100)
+    }
+
+    /// Converts a floating point rupee amount, as found in legacy documents.
+    pub fn from_f64(rupees: f64) -> Money {
+        Money((rupees * 100.0).round() as i64)
+    }
+
+    pub fn paise(&self) -> i64 {
+        self.0
+    }
+
+    pub fn rupees(&self) -> i64 {
+        self.0 / 100
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// `self * num / den`, rounded to the paisa.
+    pub fn mul_ratio(&self, num: i64, den: i64, rounding: Rounding) -> Money {
+        assert!(den > 0, "Money ratio denominator must be positive");
+        Money(rounding.div(self.0 as i128 * num as i128, den as i128) as i64)
+    }
+
+    /// `rate` percent of this amount, rounded to the paisa.
+    pub fn percent(&self, rate: f32, rounding: Rounding) -> Money {
+        let basis_points = (rate as f64 * 100.0).round() as i64;
+        self.mul_ratio(basis_points, 10_000, rounding)
+    }
+
+    pub fn round_to_rupee(&self, rounding: Rounding) -> Money {
+        Money(rounding.div(self.0 as i128, 100) as i64 * 100)
+    }
+
+    pub fn min(self, other: Money) -> Money {
+        Money(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Money) -> Money {
+        Money(self.0.max(other.0))
+    }
+}
+
+const ONES: [&str; 20] = [
+    "Zero",
+    "One",
+    "Two",
+    "Three",
+    "Four",
+    "Five",
+    "Six",
+    "Seven",
+    "Eight",
+    "Nine",
+    "Ten",
+    "Eleven",
+    "Twelve",
+    "Thirteen",
+    "Fourteen",
+    "Fifteen",
+    "Sixteen",
+    "Seventeen",
+    "Eighteen",
+    "Nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "Twenty", "Thirty", "Forty", "Fifty", "Sixty", "Seventy", "Eighty", "Ninety",
+];
+
+/// Spells out `n` using the Indian system of lakhs and crores.
+fn number_in_words(n: u64) -> String {
+    match n {
+        0..=19 => ONES[n as usize].to_string(),
+        20..=99 => match n % 10 {
+            0 => TENS[(n / 10) as usize].to_string(),
+            unit => format!("{} {}", TENS[(n / 10) as usize], ONES[unit as usize]),
+        },
+        _ => {
+            let (unit, name) = match n {
+                100..=999 => (100, "Hundred"),
+                1_000..=99_999 => (1_000, "Thousand"),
+                100_000..=9_999_999 => (100_000, "Lakh"),
+                _ => (10_000_000, "Crore"),
+            };
+            match n % unit {
+                0 => format!("{} {}", number_in_words(n / unit), name),
+                rest => format!(
+                    "{} {} {}",
+                    number_in_words(n / unit),
+                    name,
+                    number_in_words(rest)
+                ),
+            }
+        }
+    }
+}
+
+impl Money {
+    /// The amount as written on invoices, e.g. `Rupees Twelve and Fifty Paise Only`.
+    pub fn in_words(&self) -> String {
+        let paise = self.0.unsigned_abs();
+        let sign = if self.0 < 0 { "Minus " } else { "" };
+        let rupees = number_in_words(paise / 100);
+        match paise % 100 {
+            0 => format!("{}Rupees {} Only", sign, rupees),
+            rest => format!(
+                "{}Rupees {} and {} Paise Only",
+                sign,
+                rupees,
+                number_in_words(rest)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let paise = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, paise / 100, paise % 100)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        iter.copied().sum()
+    }
+}
+
+impl From<Money> for Bson {
+    fn from(value: Money) -> Self {
+        Bson::Int64(value.0)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+struct MoneyVisitor;
+
+impl<'de> de::Visitor<'de> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an integer amount of paise")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<Money, E> {
+        Ok(Money(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<Money, E> {
+        i64::try_from(value)
+            .map(Money)
+            .map_err(|_| E::custom("amount out of range"))
+    }
+}
+
+/// Reads a stored amount, converting the rupee doubles of legacy documents.
+/// Only for model fields; request bodies must carry integer paise.
+pub fn deserialize_stored<'de, D>(deserializer: D) -> std::result::Result<Money, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(StoredMoneyVisitor)
+}
+
+struct StoredMoneyVisitor;
+
+impl<'de> de::Visitor<'de> for StoredMoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an integer amount of paise or a legacy rupee amount")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<Money, E> {
+        MoneyVisitor.visit_i64(value)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<Money, E> {
+        MoneyVisitor.visit_u64(value)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> std::result::Result<Money, E> {
+        Ok(Money::from_f64(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{doc, from_bson, from_document, Bson};
+
+    use super::*;
+
+    #[test]
+    fn half_paise_round_by_mode() {
+        assert_eq!(Rounding::HalfUp.div(5, 2), 3);
+        assert_eq!(Rounding::HalfEven.div(5, 2), 2);
+        assert_eq!(Rounding::HalfEven.div(7, 2), 4);
+        assert_eq!(Rounding::Down.div(5, 2), 2);
+        assert_eq!(Rounding::Up.div(4001, 1000), 5);
+        assert_eq!(Rounding::HalfUp.div(-5, 2), -3);
+        assert_eq!(Rounding::HalfEven.div(-5, 2), -2);
+        assert_eq!(Rounding::HalfUp.div(1499, 1000), 1);
+    }
+
+    #[test]
+    fn percent_resolves_half_paise() {
+        // 9% of 2.50 is 22.5 paise.
+        let amount = Money::from_paise(250);
+        assert_eq!(amount.percent(9.0, Rounding::HalfUp), Money::from_paise(23));
+        assert_eq!(
+            amount.percent(9.0, Rounding::HalfEven),
+            Money::from_paise(22)
+        );
+        assert_eq!(
+            Money::from_rupees(1000).percent(18.0, Rounding::HalfUp),
+            Money::from_rupees(180)
+        );
+        assert_eq!(
+            Money::from_paise(1).mul_ratio(1, 2, Rounding::HalfUp),
+            Money::from_paise(1)
+        );
+    }
+
+    #[test]
+    fn round_to_rupee_at_fifty_paise() {
+        assert_eq!(
+            Money::from_paise(12350).round_to_rupee(Rounding::HalfUp),
+            Money::from_rupees(124)
+        );
+        assert_eq!(
+            Money::from_paise(12349).round_to_rupee(Rounding::HalfUp),
+            Money::from_rupees(123)
+        );
+        assert_eq!(Money::from_f64(0.1 + 0.2), Money::from_paise(30));
+    }
+
+    #[test]
+    fn amounts_in_words_use_lakhs_and_crores() {
+        assert_eq!(
+            Money::from_paise(1250).in_words(),
+            "Rupees Twelve and Fifty Paise Only"
+        );
+        assert_eq!(
+            Money::from_rupees(123_456).in_words(),
+            "Rupees One Lakh Twenty Three Thousand Four Hundred Fifty Six Only"
+        );
+        assert_eq!(
+            Money::from_rupees(12_000_000).in_words(),
+            "Rupees One Crore Twenty Lakh Only"
+        );
+        assert_eq!(
+            Money::from_paise(-50).in_words(),
+            "Minus Rupees Zero and Fifty Paise Only"
+        );
+        assert_eq!(Money::from_paise(-1205).to_string(), "-12.05");
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Stored {
+        #[serde(deserialize_with = "deserialize_stored")]
+        amount: Money,
+    }
+
+    #[test]
+    fn only_stored_amounts_accept_rupee_doubles() {
+        assert_eq!(
+            from_bson::<Money>(Bson::Int64(50025)).unwrap(),
+            Money::from_paise(50025)
+        );
+        assert!(from_bson::<Money>(Bson::Double(500.25)).is_err());
+        let stored: Stored = from_document(doc! {"amount": 500.25}).unwrap();
+        assert_eq!(stored.amount, Money::from_paise(50025));
+        let stored: Stored = from_document(doc! {"amount": 50025_i64}).unwrap();
+        assert_eq!(stored.amount, Money::from_paise(50025));
+    }
+}
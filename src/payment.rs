@@ -0,0 +1,173 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    Client, ClientSession, Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::billing::local_midnight;
+use crate::date::Date;
+use crate::dunning::reactivate_if_settled;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{CreditNote, Invoice, PaidStatus, Payment, PaymentMode};
+use crate::money::Money;
+use crate::transaction;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRequest {
+    /// Amount received, in paise.
+    pub amount: Money,
+    pub mode: PaymentMode,
+    pub reference: Option<String>,
+    /// Day the money was received; today when omitted.
+    pub date: Option<Date>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentReceipt {
+    pub payment: Payment,
+    pub amount_paid: Money,
+    pub outstanding: Money,
+    pub paid_status: PaidStatus,
+}
+
+/// Records money received against a finalized invoice and updates its balance.
+/// The check against the outstanding balance and the payment are written in one
+/// transaction, so concurrent payments cannot together overpay the invoice.
+pub async fn record_payment(
+    client: &Client,
+    db: &Database,
+    invoice: &Invoice,
+    request: PaymentRequest,
+) -> Result<PaymentReceipt> {
+    if invoice.draft {
+        return Err(Error::new(
+            "Payments cannot be recorded against a draft invoice",
+            ErrorKind::LogicalError,
+        ));
+    }
+    if !request.amount.is_positive() {
+        return Err(Error::new(
+            "Payment amount must be positive",
+            ErrorKind::InvalidData,
+        ));
+    }
+    if request.mode == PaymentMode::Wallet {
+        // Only billing debits the wallet, recording its payment with the debit.
+        return Err(Error::new(
+            "Wallet payments are applied by billing, not recorded",
+            ErrorKind::InvalidData,
+        ));
+    }
+    let now = DateTime::now();
+    let payment = Payment {
+        id: ObjectId::new(),
+        invoice: invoice.id,
+        organization: invoice.organization.clone(),
+        amount: request.amount,
+        mode: request.mode,
+        reference: request.reference,
+        date: request
+            .date
+            .map(|date| local_midnight(date.value()))
+            .unwrap_or(now),
+        created_at: now,
+    };
+    let mut attempt = 1;
+    loop {
+        let mut session = transaction::start(client).await?;
+        let result = pay_with_session(db, &mut session, &payment).await;
+        match transaction::finish(&mut session, result).await {
+            Err(err) if transaction::should_retry(&err, attempt) => attempt += 1,
+            Err(err) => return Err(err),
+            Ok(invoice) => {
+                if invoice.paid_status == PaidStatus::Paid {
+                    reactivate_if_settled(db, &invoice.organization).await?;
+                }
+                return Ok(PaymentReceipt {
+                    payment,
+                    amount_paid: invoice.amount_paid,
+                    outstanding: invoice.outstanding(),
+                    paid_status: invoice.paid_status,
+                });
+            }
+        }
+    }
+}
+
+async fn pay_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    payment: &Payment,
+) -> Result<Invoice> {
+    // The balance is read inside the transaction, not from the request's copy.
+    let invoice = Invoice::collection(db)
+        .find_one_with_session(doc! {"_id": payment.invoice}, None, session)
+        .await?
+        .ok_or_else(|| Error::new("Invoice not found", ErrorKind::NotFound))?;
+    if payment.amount > invoice.outstanding() {
+        return Err(Error::new(
+            format!(
+                "Payment of {} exceeds the outstanding balance of {}",
+                payment.amount,
+                invoice.outstanding()
+            ),
+            ErrorKind::LogicalError,
+        ));
+    }
+    Payment::collection(db)
+        .insert_one_with_session(payment, None, session)
+        .await?;
+    // Also writes the invoice, so a concurrent payment on it conflicts and retries.
+    refresh_balance_with_session(db, session, &invoice).await
+}
+
+/// Recomputes the balance of `invoice` from its recorded payments and credit notes,
+/// inside the transaction of `session`. Writing the invoice makes concurrent
+/// transactions on it conflict, so one of them retries and sees what the other
+/// recorded. Reactivation is left to the caller, once the transaction has committed.
+pub async fn refresh_balance_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    invoice: &Invoice,
+) -> Result<Invoice> {
+    let amount_paid: Money = Payment::collection(db)
+        .find_with_session(doc! {"invoice": invoice.id}, None, session)
+        .await?
+        .stream(session)
+        .try_collect::<Vec<Payment>>()
+        .await?
+        .iter()
+        .map(|payment| payment.amount)
+        .sum();
+    let credited_value: Money = CreditNote::collection(db)
+        .find_with_session(doc! {"invoice": invoice.id}, None, session)
+        .await?
+        .stream(session)
+        .try_collect::<Vec<CreditNote>>()
+        .await?
+        .iter()
+        .map(|credit_note| credit_note.rounded_value)
+        .sum();
+    let mut invoice = invoice.clone();
+    invoice.amount_paid = amount_paid;
+    invoice.credited_value = credited_value;
+    invoice.paid_status = invoice.settled_status();
+    invoice.updated_at = DateTime::now();
+    Invoice::collection(db)
+        .update_one_with_session(
+            doc! {"_id": invoice.id},
+            doc! {"$set": {
+                "amountPaid": invoice.amount_paid,
+                "creditedValue": invoice.credited_value,
+                "paidStatus": invoice.paid_status,
+                "updatedAt": invoice.updated_at,
+            }},
+            None,
+            session,
+        )
+        .await?;
+    Ok(invoice)
+}
@@ -0,0 +1,257 @@
+use printpdf::{BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point};
+
+use crate::billing::local_date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{Invoice, Organization, OrganizationAddress};
+use crate::money::Money;
+use crate::supplier::Supplier;
+use crate::tax::{state_name, SAC_CODE};
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const LINE_HEIGHT: f32 = 5.0;
+
+impl From<printpdf::Error> for Error {
+    fn from(err: printpdf::Error) -> Self {
+        Error::new(err.to_string(), ErrorKind::Internal)
+    }
+}
+
+/// Writes text top-down on A4 pages, starting a new page when one fills up.
+struct Writer {
+    doc: printpdf::PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    y: f32,
+}
+
+impl Writer {
+    fn new(title: &str) -> Result<Writer> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Invoice");
+        let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(Writer {
+            doc,
+            layer,
+            regular,
+            bold,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    fn ensure_space(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            let (page, layer) = self
+                .doc
+                .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Invoice");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&self, text: &str, size: f32, x: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    /// Right-aligns `text` at `right`, estimating Helvetica's average glyph width.
+    fn text_right(&self, text: &str, size: f32, right: f32, bold: bool) {
+        let width = text.chars().count() as f32 * size * 0.556 * 0.3528;
+        self.text(text, size, right - width, bold);
+    }
+
+    fn rule(&self) {
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGIN), Mm(self.y)), false),
+                (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(self.y)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    fn advance(&mut self, lines: f32) {
+        self.y -= LINE_HEIGHT * lines;
+    }
+
+    fn finish(self) -> Result<Vec<u8>> {
+        Ok(self.doc.save_to_bytes()?)
+    }
+}
+
+fn address_lines(address: &OrganizationAddress) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(street) = &address.street {
+        lines.push(street.clone());
+    }
+    let locality = [&address.city, &address.pin_code]
+        .iter()
+        .filter_map(|part| part.as_deref())
+        .collect::<Vec<_>>()
+        .join(" - ");
+    if !locality.is_empty() {
+        lines.push(locality);
+    }
+    let region = [address.state.as_deref(), Some(address.country.as_str())]
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    lines.push(region);
+    lines
+}
+
+fn amount(value: Money) -> String {
+    format!("Rs. {}", value)
+}
+
+/// Renders `invoice` as a printable GST tax invoice.
+pub fn render_invoice(
+    invoice: &Invoice,
+    organization: &Organization,
+    supplier: &Supplier,
+) -> Result<Vec<u8>> {
+    let title = if invoice.draft {
+        "DRAFT TAX INVOICE"
+    } else {
+        "TAX INVOICE"
+    };
+    let mut w = Writer::new(title)?;
+    let right = PAGE_WIDTH - MARGIN;
+
+    w.text(title, 16.0, MARGIN, true);
+    w.advance(2.0);
+    w.text(&supplier.name, 11.0, MARGIN, true);
+    w.text_right(
+        &format!(
+            "Invoice No: {}",
+            invoice.invoice_no.as_deref().unwrap_or("-")
+        ),
+        9.0,
+        right,
+        false,
+    );
+    w.advance(1.0);
+    for line in supplier.address.split('\n') {
+        w.text(line.trim(), 9.0, MARGIN, false);
+        w.advance(1.0);
+    }
+    w.text(&format!("GSTIN: {}", supplier.gst_no), 9.0, MARGIN, false);
+    // Dated by issue, as the e-invoice is; a draft has not been issued yet.
+    let issued_on = invoice
+        .issued_at
+        .map(|issued_at| local_date(issued_at).format("%d-%m-%Y").to_string());
+    w.text_right(
+        &format!("Date: {}", issued_on.as_deref().unwrap_or("-")),
+        9.0,
+        right,
+        false,
+    );
+    if let Some(billing_period) = &invoice.billing_period {
+        w.advance(1.0);
+        w.text_right(
+            &format!("Billing Period: {}", billing_period),
+            9.0,
+            right,
+            false,
+        );
+    }
+    w.advance(2.0);
+
+    w.text("Bill To", 9.0, MARGIN, true);
+    w.advance(1.0);
+    w.text(&invoice.billed_to, 10.0, MARGIN, true);
+    w.advance(1.0);
+    for line in address_lines(&organization.billing_address) {
+        w.text(&line, 9.0, MARGIN, false);
+        w.advance(1.0);
+    }
+    if let Some(gst_no) = &organization.gst_no {
+        w.text(&format!("GSTIN: {}", gst_no), 9.0, MARGIN, false);
+        w.advance(1.0);
+    }
+    if let Some(place_of_supply) = &invoice.place_of_supply {
+        let state = state_name(place_of_supply).unwrap_or_default();
+        w.text(
+            &format!("Place of Supply: {} ({})", state, place_of_supply),
+            9.0,
+            MARGIN,
+            false,
+        );
+        w.advance(1.0);
+    }
+    w.advance(1.0);
+
+    w.rule();
+    w.advance(1.0);
+    w.text("#", 9.0, MARGIN, true);
+    w.text("Description", 9.0, MARGIN + 8.0, true);
+    w.text("SAC", 9.0, MARGIN + 95.0, true);
+    w.text("Period", 9.0, MARGIN + 112.0, true);
+    w.text_right("Amount", 9.0, right, true);
+    w.advance(0.6);
+    w.rule();
+    w.advance(1.2);
+    for (index, usage) in invoice.organization_usage.iter().enumerate() {
+        w.ensure_space(LINE_HEIGHT * 2.0);
+        w.text(&(index + 1).to_string(), 9.0, MARGIN, false);
+        w.text(&usage.description(), 9.0, MARGIN + 8.0, false);
+        w.text(SAC_CODE, 9.0, MARGIN + 95.0, false);
+        w.text(&usage.billing_period, 8.0, MARGIN + 112.0, false);
+        w.text_right(&usage.amount().to_string(), 9.0, right, false);
+        w.advance(1.0);
+    }
+    w.rule();
+    w.advance(1.2);
+
+    let round_off = invoice.rounded_value - invoice.total_value;
+    let mut totals = vec![("Taxable Value", invoice.service_value)];
+    let rate = if !invoice.cgst_value.is_zero() || !invoice.sgst_value.is_zero() {
+        let half_rate = invoice.tax_ratio / 2.0;
+        totals.push(("CGST", invoice.cgst_value));
+        totals.push(("SGST", invoice.sgst_value));
+        format!("CGST @ {}% + SGST @ {}%", half_rate, half_rate)
+    } else if !invoice.igst_value.is_zero() {
+        totals.push(("IGST", invoice.igst_value));
+        format!("IGST @ {}%", invoice.tax_ratio)
+    } else {
+        // Invoices issued before the split only record the combined tax.
+        totals.push(("GST", invoice.tax_value));
+        format!("GST @ {}%", invoice.tax_ratio)
+    };
+    w.ensure_space(LINE_HEIGHT * 8.0);
+    w.text(&rate, 8.0, MARGIN, false);
+    totals.push(("Total", invoice.total_value));
+    totals.push(("Round Off", round_off));
+    for (label, value) in totals {
+        w.text(label, 9.0, right - 70.0, false);
+        w.text_right(&amount(value), 9.0, right, false);
+        w.advance(1.0);
+    }
+    w.rule();
+    w.advance(1.2);
+    w.text("Invoice Total", 10.0, right - 70.0, true);
+    w.text_right(&amount(invoice.rounded_value), 10.0, right, true);
+    w.advance(2.0);
+    w.ensure_space(LINE_HEIGHT * 2.0);
+    w.text(
+        &format!("Amount in words: {}", invoice.rounded_value.in_words()),
+        9.0,
+        MARGIN,
+        false,
+    );
+    w.advance(3.0);
+    w.ensure_space(LINE_HEIGHT * 2.0);
+    w.text(
+        "This is a computer generated invoice and does not require a signature.",
+        8.0,
+        MARGIN,
+        false,
+    );
+    w.finish()
+}
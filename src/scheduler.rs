@@ -0,0 +1,342 @@
+use chrono::{Datelike, Duration, TimeZone, Timelike};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::UpdateOptions,
+    Client, Database,
+};
+
+use crate::billing::{billing_cutoff, ist};
+use crate::error::{Error, ErrorKind, Result};
+use crate::invoicing;
+use crate::lease::{LeaseConfig, BILLING_LEASE, LEASE_HELD};
+use crate::model::{BillingRunStatus, ScheduledJob};
+use crate::sequence::Numberings;
+use crate::supplier::Supplier;
+
+const BILLING_JOB: &str = "BILLING";
+
+/// Wait before retrying a scheduled run that failed.
+const RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// A cron expression, `minute hour day-of-month month day-of-week`, read in IST.
+/// Each field is `*`, a value, a range `a-b`, a step `*/n` or `a-b/n`, or a comma
+/// separated list of those. Day of week runs from 0 (Sunday) to 6.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    days_of_week: Vec<u32>,
+    /// Cron matches either day field when both are restricted.
+    any_day: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>> {
+    let invalid = || {
+        Error::new(
+            format!("Invalid schedule field {:?}", field),
+            ErrorKind::InvalidData,
+        )
+    };
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u32>().map_err(|_| invalid())?,
+                    end.parse::<u32>().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let value = range.parse::<u32>().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> Result<Schedule> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(Error::new(
+                format!("Schedule {:?} must have five fields", expression),
+                ErrorKind::InvalidData,
+            ));
+        }
+        Ok(Schedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week: parse_field(fields[4], 0, 6)?,
+            any_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    /// Reads `BILLING_SCHEDULE`, by default 00:05 IST on the 1st of each month.
+    /// `off` disables scheduled billing.
+    pub fn from_env() -> Option<Schedule> {
+        let expression =
+            std::env::var("BILLING_SCHEDULE").unwrap_or_else(|_| String::from("5 0 1 * *"));
+        if expression.trim().eq_ignore_ascii_case("off") {
+            return None;
+        }
+        Some(Schedule::parse(&expression).expect("BILLING_SCHEDULE must be a cron expression"))
+    }
+
+    fn day_matches(&self, day_of_month: u32, day_of_week: u32) -> bool {
+        let by_month = self.days_of_month.contains(&day_of_month);
+        let by_week = self.days_of_week.contains(&day_of_week);
+        if self.any_day {
+            by_month || by_week
+        } else {
+            by_month && by_week
+        }
+    }
+
+    /// First minute matching the schedule strictly after `after`, if any within five years.
+    pub fn next_after(&self, after: DateTime) -> Option<DateTime> {
+        let after = after.to_chrono().with_timezone(&ist());
+        let mut at = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = at + Duration::days(5 * 366);
+        while at < limit {
+            if !self.months.contains(&at.month())
+                || !self.day_matches(at.day(), at.weekday().num_days_from_sunday())
+            {
+                let next_day = at.date_naive().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                at = ist().from_local_datetime(&next_day).unwrap();
+            } else if !self.hours.contains(&at.hour()) {
+                at += Duration::minutes(60 - at.minute() as i64);
+            } else if !self.minutes.contains(&at.minute()) {
+                at += Duration::minutes(1);
+            } else {
+                return Some(DateTime::from_chrono(at));
+            }
+        }
+        None
+    }
+}
+
+async fn last_run_at(db: &Database) -> Result<Option<DateTime>> {
+    Ok(ScheduledJob::collection(db)
+        .find_one(doc! {"_id": BILLING_JOB}, None)
+        .await?
+        .map(|job| job.last_run_at))
+}
+
+async fn record_run(db: &Database, run_at: DateTime, billing_run: ObjectId) -> Result<()> {
+    ScheduledJob::collection(db)
+        .update_one(
+            doc! {"_id": BILLING_JOB},
+            doc! {"$set": {
+                "lastRunAt": run_at,
+                "billingRun": billing_run,
+                "updatedAt": DateTime::now(),
+            }},
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn sleep_until(at: DateTime) {
+    let millis = at.timestamp_millis() - DateTime::now().timestamp_millis();
+    if millis > 0 {
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(millis as u64)).await;
+    }
+}
+
+/// Bills if the run due at `due` has not been made yet, by this or another instance.
+/// The billing run, once queued, is left in `queued` so it can be closed should
+/// this be stopped midway.
+async fn run_if_due(
+    client: &Client,
+    db: &Database,
+    supplier: &Supplier,
+    numberings: &Numberings,
+    due: DateTime,
+    queued: &mut Option<ObjectId>,
+) -> Result<()> {
+    if matches!(last_run_at(db).await?, Some(last_run_at) if last_run_at >= due) {
+        return Ok(());
+    }
+    let now = DateTime::now();
+    let run = invoicing::queue(db, billing_cutoff(now)).await?;
+    *queued = Some(run.id);
+    let run = invoicing::execute(client, db, supplier, numberings, run.id).await?;
+    println!(
+        "Scheduled billing run {}: {} invoiced, {} skipped, {} failed",
+        run.id,
+        run.invoiced.len(),
+        run.skipped.len(),
+        run.failed.len()
+    );
+    if run.status != BillingRunStatus::Completed {
+        // Not recorded as made, so the run is retried.
+        return Err(Error::new(
+            format!(
+                "Scheduled billing run {} did not complete: {}",
+                run.id,
+                run.error.as_deref().unwrap_or("cancelled")
+            ),
+            ErrorKind::Internal,
+        ));
+    }
+    record_run(db, now, run.id).await
+}
+
+/// Starts billing runs on `schedule` for as long as the server is up. A run missed
+/// while the server was down is made up once on startup; since a run bills every
+/// month before the current one, one catch-up covers any number of missed runs.
+/// Only the instance holding the billing lease runs it.
+pub async fn run_billing(
+    client: Client,
+    db: Database,
+    supplier: Supplier,
+    numberings: Numberings,
+    leases: LeaseConfig,
+    schedule: Schedule,
+) {
+    // Never run before: start from the next scheduled time.
+    let started = DateTime::now();
+    loop {
+        let after = match last_run_at(&db).await {
+            Ok(last_run_at) => last_run_at.unwrap_or(started),
+            Err(err) => {
+                println!("Billing schedule unavailable: {}", err);
+                actix_web::rt::time::sleep(RETRY_AFTER).await;
+                continue;
+            }
+        };
+        let due = match schedule.next_after(after) {
+            Some(due) => due,
+            None => {
+                println!("Billing schedule never fires; scheduled billing stopped");
+                return;
+            }
+        };
+        // A due time already past is a missed run, made up right away.
+        sleep_until(due).await;
+        let mut queued = None;
+        let run = run_if_due(&client, &db, &supplier, &numberings, due, &mut queued);
+        let result = leases.run(&db, BILLING_LEASE, run).await;
+        if let (Err(err), Some(id)) = (&result, queued) {
+            // A run stopped midway, as by a lost lease, has not closed itself.
+            invoicing::abandon(&db, id, err).await;
+        }
+        match result {
+            Ok(()) => {}
+            Err(err) if err.code() == LEASE_HELD => {
+                // Another instance is billing; check back for its record.
+                actix_web::rt::time::sleep(leases.ttl).await;
+            }
+            Err(err) => {
+                println!("Scheduled billing run failed: {}", err);
+                actix_web::rt::time::sleep(RETRY_AFTER).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32, hour: u32, minute: u32) -> DateTime {
+        let local = NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+        DateTime::from_chrono(ist().from_local_datetime(&local).unwrap())
+    }
+
+    fn next(expression: &str, after: DateTime) -> Option<DateTime> {
+        Schedule::parse(expression).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parse_rejects_malformed_expressions() {
+        assert!(Schedule::parse("5 0 1 *").is_err());
+        assert!(Schedule::parse("60 0 1 * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("0 0 0 * *").is_err());
+        assert!(Schedule::parse("0 0 * * 7").is_err());
+        assert!(Schedule::parse("0 0 5-1 * *").is_err());
+        let schedule = Schedule::parse("*/15 9-17/4 1,15 * 1-5").unwrap();
+        assert_eq!(schedule.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(schedule.hours, vec![9, 13, 17]);
+        assert_eq!(schedule.days_of_month, vec![1, 15]);
+        assert_eq!(schedule.days_of_week, vec![1, 2, 3, 4, 5]);
+        assert!(schedule.any_day);
+    }
+
+    #[test]
+    fn next_after_is_strictly_later() {
+        assert_eq!(
+            next("5 0 1 * *", at(2026, 10, 18, 12, 0)),
+            Some(at(2026, 11, 1, 0, 5))
+        );
+        assert_eq!(
+            next("5 0 1 * *", at(2026, 11, 1, 0, 5)),
+            Some(at(2026, 12, 1, 0, 5))
+        );
+        assert_eq!(
+            next("5 0 1 * *", at(2026, 12, 15, 0, 0)),
+            Some(at(2027, 1, 1, 0, 5))
+        );
+    }
+
+    #[test]
+    fn day_of_month_skips_months_without_it() {
+        assert_eq!(
+            next("0 0 31 * *", at(2026, 4, 1, 0, 0)),
+            Some(at(2026, 5, 31, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", at(2026, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn day_of_week_counts_from_sunday() {
+        // 18 October 2026 is a Sunday.
+        assert_eq!(
+            next("0 9 * * 0", at(2026, 10, 17, 12, 0)),
+            Some(at(2026, 10, 18, 9, 0))
+        );
+        assert_eq!(
+            next("0 9 * * 5", at(2026, 10, 18, 12, 0)),
+            Some(at(2026, 10, 23, 9, 0))
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 20th is a Tuesday, before the next Friday.
+        assert_eq!(
+            next("0 9 20 * 5", at(2026, 10, 18, 12, 0)),
+            Some(at(2026, 10, 20, 9, 0))
+        );
+        assert_eq!(
+            next("0 9 20 * 5", at(2026, 10, 20, 9, 0)),
+            Some(at(2026, 10, 23, 9, 0))
+        );
+    }
+}
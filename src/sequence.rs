@@ -0,0 +1,177 @@
+use chrono::{Datelike, NaiveDate};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    ClientSession, Database,
+};
+
+use crate::billing::local_date;
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::Sequence;
+
+/// Indian financial year (April to March) containing `date`, e.g. `2026-27`.
+pub fn financial_year(date: NaiveDate) -> String {
+    let start = if date.month() >= 4 {
+        date.year()
+    } else {
+        date.year() - 1
+    };
+    format!("{}-{:02}", start, (start + 1) % 100)
+}
+
+fn increment_options() -> FindOneAndUpdateOptions {
+    FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build()
+}
+
+fn allocated(key: &str, sequence: Option<Sequence>) -> Result<i64> {
+    sequence.map(|sequence| sequence.value).ok_or_else(|| {
+        Error::new(
+            format!("Sequence {} not allocated", key),
+            ErrorKind::Internal,
+        )
+    })
+}
+
+/// Atomically takes the next value of the counter `key`, starting from 1.
+pub async fn next_value(db: &Database, key: &str) -> Result<i64> {
+    let sequence = Sequence::collection(db)
+        .find_one_and_update(
+            doc! {"_id": key},
+            doc! {"$inc": {"value": 1_i64}},
+            increment_options(),
+        )
+        .await?;
+    allocated(key, sequence)
+}
+
+/// Same as [`next_value`], but inside the transaction of `session`, so the value
+/// is given back if the transaction aborts.
+pub async fn next_value_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    key: &str,
+) -> Result<i64> {
+    let sequence = Sequence::collection(db)
+        .find_one_and_update_with_session(
+            doc! {"_id": key},
+            doc! {"$inc": {"value": 1_i64}},
+            increment_options(),
+            session,
+        )
+        .await?;
+    allocated(key, sequence)
+}
+
+/// Layout of document numbers. `{fy}` is replaced by the financial year and
+/// `{seq}` by the sequence value; `{seq:06}` pads it with zeros to six digits.
+#[derive(Debug, Clone)]
+pub struct Numbering {
+    pub key: &'static str,
+    pub format: String,
+}
+
+impl Numbering {
+    pub fn render(&self, fy: &str, seq: i64) -> String {
+        let mut rendered = self.format.replace("{fy}", fy);
+        while let Some(start) = rendered.find("{seq") {
+            let end = match rendered[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            let width = rendered[start + 4..end]
+                .trim_start_matches(':')
+                .parse::<usize>()
+                .unwrap_or(0);
+            rendered.replace_range(start..=end, &format!("{:0width$}", seq, width = width));
+        }
+        rendered
+    }
+
+    /// Allocates the next number in the financial year of `date`.
+    pub async fn next(&self, db: &Database, date: DateTime) -> Result<String> {
+        let fy = financial_year(local_date(date));
+        let seq = next_value(db, &format!("{}/{}", self.key, fy)).await?;
+        Ok(self.render(&fy, seq))
+    }
+
+    /// Allocates the next number inside the transaction of `session`.
+    pub async fn next_with_session(
+        &self,
+        db: &Database,
+        session: &mut ClientSession,
+        date: DateTime,
+    ) -> Result<String> {
+        let fy = financial_year(local_date(date));
+        let seq = next_value_with_session(db, session, &format!("{}/{}", self.key, fy)).await?;
+        Ok(self.render(&fy, seq))
+    }
+}
+
+/// Numbering of every document series the service issues.
+#[derive(Debug, Clone)]
+pub struct Numberings {
+    pub invoice: Numbering,
+    pub credit_note: Numbering,
+}
+
+impl Numberings {
+    pub fn from_env() -> Numberings {
+        Numberings {
+            invoice: Numbering {
+                key: "INVOICE",
+                format: std::env::var("INVOICE_NO_FORMAT")
+                    .unwrap_or_else(|_| String::from("AP/{fy}/{seq:06}")),
+            },
+            credit_note: Numbering {
+                key: "CREDIT_NOTE",
+                format: std::env::var("CREDIT_NOTE_NO_FORMAT")
+                    .unwrap_or_else(|_| String::from("CN/{fy}/{seq:06}")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn financial_year_rolls_over_in_april() {
+        assert_eq!(
+            financial_year(NaiveDate::from_ymd_opt(2026, 3, 31).unwrap()),
+            "2025-26"
+        );
+        assert_eq!(
+            financial_year(NaiveDate::from_ymd_opt(2026, 4, 1).unwrap()),
+            "2026-27"
+        );
+        assert_eq!(
+            financial_year(NaiveDate::from_ymd_opt(2026, 12, 31).unwrap()),
+            "2026-27"
+        );
+        assert_eq!(
+            financial_year(NaiveDate::from_ymd_opt(2099, 4, 1).unwrap()),
+            "2099-00"
+        );
+    }
+
+    #[test]
+    fn render_substitutes_year_and_padded_sequence() {
+        let numbering = |format: &str| Numbering {
+            key: "INVOICE",
+            format: String::from(format),
+        };
+        assert_eq!(
+            numbering("AP/{fy}/{seq:06}").render("2026-27", 42),
+            "AP/2026-27/000042"
+        );
+        assert_eq!(numbering("CN-{seq}").render("2026-27", 7), "CN-7");
+        assert_eq!(
+            numbering("{seq:3}-{fy}").render("2025-26", 1234),
+            "1234-2025-26"
+        );
+    }
+}
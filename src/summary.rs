@@ -0,0 +1,146 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneOptions, FindOptions},
+    Database,
+};
+use serde::Serialize;
+
+use crate::billing::{local_date, local_midnight, next_month_start};
+use crate::catalog::PricingCatalog;
+use crate::error::Result;
+use crate::invoicing;
+use crate::metering;
+use crate::model::{
+    Invoice, Organization, OrganizationPricing, OrganizationPricingAdditions,
+    OrganizationPricingTier, OrganizationStatus, PaidStatus, Payment,
+};
+use crate::money::Money;
+use crate::supplier::Supplier;
+use crate::wallet;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BillingSummary {
+    pub organization: String,
+    pub tier: OrganizationPricingTier,
+    pub pricing: OrganizationPricing,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub additions: Option<OrganizationPricingAdditions>,
+    pub wallet_balance: Money,
+    pub status: OrganizationStatus,
+    pub grace_period: u8,
+    pub outstanding_invoices: Vec<Invoice>,
+    pub total_outstanding: Money,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_payment: Option<Payment>,
+    /// The open draft recomputed with the usage reported so far, as the next
+    /// monthly run finalizes it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_draft: Option<Invoice>,
+    /// The draft the next monthly run writes after it, if the organization is billed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projected_invoice: Option<Invoice>,
+}
+
+/// Where `organization` stands: its plan, wallet, unpaid invoices and what it
+/// will be invoiced next. Only reads; nothing is written.
+pub async fn billing_summary(
+    db: &Database,
+    supplier: &Supplier,
+    organization: &Organization,
+) -> Result<BillingSummary> {
+    let outstanding_invoices = Invoice::collection(db)
+        .find(
+            doc! {
+                "organization": &organization.name,
+                "draft": false,
+                "paidStatus": {"$in": [PaidStatus::Unpaid, PaidStatus::PartiallyPaid]},
+            },
+            FindOptions::builder().sort(doc! {"date": 1}).build(),
+        )
+        .await?
+        .try_collect::<Vec<Invoice>>()
+        .await?;
+    let last_payment = Payment::collection(db)
+        .find_one(
+            doc! {"organization": &organization.name},
+            FindOneOptions::builder()
+                .sort(doc! {"date": -1, "createdAt": -1})
+                .build(),
+        )
+        .await?;
+    let catalog = PricingCatalog::load(db).await?;
+    let (open_draft, projected_invoice) = match invoicing::skip_reason(organization) {
+        Some(_) => (None, None),
+        None => next_invoices(db, supplier, &catalog, organization).await?,
+    };
+    Ok(BillingSummary {
+        organization: organization.name.clone(),
+        tier: organization.pricing,
+        pricing: catalog.resolve(organization.pricing, DateTime::now()),
+        additions: organization.additions,
+        wallet_balance: wallet::balance(organization),
+        status: organization.status,
+        grace_period: organization.grace_period,
+        total_outstanding: outstanding_invoices
+            .iter()
+            .map(|invoice| invoice.outstanding())
+            .sum(),
+        outstanding_invoices,
+        last_payment,
+        open_draft,
+        projected_invoice,
+    })
+}
+
+/// What the next monthly run writes for `organization`: its open draft, finalized
+/// over its own period with the usage reported since, and the draft for the rest
+/// of the time through the next run.
+async fn next_invoices(
+    db: &Database,
+    supplier: &Supplier,
+    catalog: &PricingCatalog,
+    organization: &Organization,
+) -> Result<(Option<Invoice>, Option<Invoice>)> {
+    let latest = invoicing::latest_invoice(db, organization).await?;
+    let open_draft = match &latest {
+        Some(draft) if draft.draft => match draft.period_start {
+            Some(period_start) => {
+                let metered =
+                    metering::usage_records(db, &organization.name, period_start, draft.date)
+                        .await?;
+                let mut open_draft = invoicing::draft_invoice(
+                    supplier,
+                    catalog,
+                    organization,
+                    &metered,
+                    period_start,
+                    draft.date,
+                );
+                open_draft.id = draft.id;
+                open_draft.created_at = draft.created_at;
+                Some(open_draft)
+            }
+            // Drafts written before periods were recorded are finalized as they are.
+            None => Some(draft.clone()),
+        },
+        _ => None,
+    };
+    let from_date = latest.map_or(organization.book_begin, |invoice| invoice.date);
+    let to_date = local_midnight(next_month_start(local_date(DateTime::now())));
+    let projected_invoice = if from_date < to_date {
+        let metered = metering::usage_records(db, &organization.name, from_date, to_date).await?;
+        Some(invoicing::draft_invoice(
+            supplier,
+            catalog,
+            organization,
+            &metered,
+            from_date,
+            to_date,
+        ))
+    } else {
+        None
+    };
+    Ok((open_draft, projected_invoice))
+}
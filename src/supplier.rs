@@ -0,0 +1,34 @@
+use crate::tax::state_code;
+
+/// The business issuing the invoices, configured through the environment.
+#[derive(Debug, Clone)]
+pub struct Supplier {
+    pub name: String,
+    pub address: String,
+    pub city: Option<String>,
+    pub pin_code: Option<String>,
+    pub gst_no: String,
+    /// GST state code of the supplier's registration.
+    pub state: String,
+}
+
+impl Supplier {
+    pub fn from_env() -> Supplier {
+        let gst_no = std::env::var("SUPPLIER_GST_NO").expect("SUPPLIER_GST_NO not set");
+        let state = match std::env::var("SUPPLIER_STATE") {
+            Ok(state) => state_code(&state).expect("SUPPLIER_STATE is not a known state"),
+            Err(_) => gst_no
+                .get(0..2)
+                .and_then(state_code)
+                .expect("SUPPLIER_GST_NO does not start with a state code"),
+        };
+        Supplier {
+            name: std::env::var("SUPPLIER_NAME").expect("SUPPLIER_NAME not set"),
+            address: std::env::var("SUPPLIER_ADDRESS").unwrap_or_default(),
+            city: std::env::var("SUPPLIER_CITY").ok(),
+            pin_code: std::env::var("SUPPLIER_PIN_CODE").ok(),
+            gst_no,
+            state: state.to_string(),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use serde::Serialize;
+
+use crate::model::{Invoice, OrganizationAddress};
+use crate::money::{Money, Rounding};
+
+/// GST rate applied to subscription services, in percent.
+pub const GST_RATE: f32 = 18.0;
+
+/// Services accounting code for licensing the right to use software.
+pub const SAC_CODE: &str = "997331";
+
+/// GST state codes as used in the first two digits of a GSTIN.
+const STATES: [(&str, &str); 38] = [
+    ("01", "Jammu and Kashmir"),
+    ("02", "Himachal Pradesh"),
+    ("03", "Punjab"),
+    ("04", "Chandigarh"),
+    ("05", "Uttarakhand"),
+    ("06", "Haryana"),
+    ("07", "Delhi"),
+    ("08", "Rajasthan"),
+    ("09", "Uttar Pradesh"),
+    ("10", "Bihar"),
+    ("11", "Sikkim"),
+    ("12", "Arunachal Pradesh"),
+    ("13", "Nagaland"),
+    ("14", "Manipur"),
+    ("15", "Mizoram"),
+    ("16", "Tripura"),
+    ("17", "Meghalaya"),
+    ("18", "Assam"),
+    ("19", "West Bengal"),
+    ("20", "Jharkhand"),
+    ("21", "Odisha"),
+    ("22", "Chhattisgarh"),
+    ("23", "Madhya Pradesh"),
+    ("24", "Gujarat"),
+    ("26", "Dadra and Nagar Haveli and Daman and Diu"),
+    ("27", "Maharashtra"),
+    ("29", "Karnataka"),
+    ("30", "Goa"),
+    ("31", "Lakshadweep"),
+    ("32", "Kerala"),
+    ("33", "Tamil Nadu"),
+    ("34", "Puducherry"),
+    ("35", "Andaman and Nicobar Islands"),
+    ("36", "Telangana"),
+    ("37", "Andhra Pradesh"),
+    ("38", "Ladakh"),
+    ("96", "Other Country"),
+    ("97", "Other Territory"),
+];
+
+/// Resolves a state given either as a GST state code or by name.
+pub fn state_code(state: &str) -> Option<&'static str> {
+    let state = state.trim();
+    STATES
+        .iter()
+        .find(|(code, name)| *code == state || name.eq_ignore_ascii_case(state))
+        .map(|(code, _)| *code)
+}
+
+pub fn state_name(code: &str) -> Option<&'static str> {
+    STATES
+        .iter()
+        .find(|(state_code, _)| *state_code == code)
+        .map(|(_, name)| *name)
+}
+
+/// State code of the place of supply: the billing address state, falling back to
+/// the recipient's GSTIN. Supplies billed outside India are reported as "96".
+pub fn place_of_supply(address: &OrganizationAddress, gst_no: Option<&str>) -> Option<String> {
+    if !address.country.eq_ignore_ascii_case("india") && !address.country.eq_ignore_ascii_case("in")
+    {
+        return Some(String::from("96"));
+    }
+    address
+        .state
+        .as_deref()
+        .and_then(state_code)
+        .or_else(|| {
+            gst_no
+                .and_then(|gst_no| gst_no.get(0..2))
+                .and_then(state_code)
+        })
+        .map(String::from)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SupplyType {
+    IntraState,
+    InterState,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxBreakup {
+    pub tax_ratio: f32,
+    pub cgst_value: Money,
+    pub sgst_value: Money,
+    pub igst_value: Money,
+}
+
+impl TaxBreakup {
+    pub fn total(&self) -> Money {
+        self.cgst_value + self.sgst_value + self.igst_value
+    }
+}
+
+pub fn supply_type(supplier_state: &str, place_of_supply: Option<&str>) -> SupplyType {
+    match place_of_supply {
+        Some(place_of_supply) if place_of_supply == supplier_state => SupplyType::IntraState,
+        // Without a known place of supply the invoice is billed as inter-state.
+        _ => SupplyType::InterState,
+    }
+}
+
+/// Supply type an issued invoice was taxed under.
+pub fn invoice_supply_type(invoice: &Invoice) -> SupplyType {
+    if !invoice.cgst_value.is_zero() || !invoice.sgst_value.is_zero() {
+        SupplyType::IntraState
+    } else {
+        SupplyType::InterState
+    }
+}
+
+pub fn compute_tax(service_value: Money, tax_ratio: f32, supply_type: SupplyType) -> TaxBreakup {
+    match supply_type {
+        SupplyType::IntraState => {
+            let half = service_value.percent(tax_ratio / 2.0, Rounding::HalfUp);
+            TaxBreakup {
+                tax_ratio,
+                cgst_value: half,
+                sgst_value: half,
+                igst_value: Money::ZERO,
+            }
+        }
+        SupplyType::InterState => TaxBreakup {
+            tax_ratio,
+            cgst_value: Money::ZERO,
+            sgst_value: Money::ZERO,
+            igst_value: service_value.percent(tax_ratio, Rounding::HalfUp),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intra_state_splits_the_rate_between_cgst_and_sgst() {
+        let breakup = compute_tax(Money::from_rupees(1000), GST_RATE, SupplyType::IntraState);
+        assert_eq!(breakup.cgst_value, Money::from_rupees(90));
+        assert_eq!(breakup.sgst_value, Money::from_rupees(90));
+        assert_eq!(breakup.igst_value, Money::ZERO);
+        assert_eq!(breakup.total(), Money::from_rupees(180));
+    }
+
+    #[test]
+    fn inter_state_charges_the_full_rate_as_igst() {
+        let breakup = compute_tax(Money::from_rupees(1000), GST_RATE, SupplyType::InterState);
+        assert_eq!(breakup.cgst_value, Money::ZERO);
+        assert_eq!(breakup.sgst_value, Money::ZERO);
+        assert_eq!(breakup.igst_value, Money::from_rupees(180));
+    }
+
+    #[test]
+    fn each_half_rounds_its_own_half_paisa() {
+        // 9% of 1.50 is 13.5 paise, rounded up on both halves; 18% is exactly 27.
+        let service_value = Money::from_paise(150);
+        let intra = compute_tax(service_value, GST_RATE, SupplyType::IntraState);
+        assert_eq!(intra.cgst_value, Money::from_paise(14));
+        assert_eq!(intra.total(), Money::from_paise(28));
+        let inter = compute_tax(service_value, GST_RATE, SupplyType::InterState);
+        assert_eq!(inter.total(), Money::from_paise(27));
+    }
+
+    #[test]
+    fn supply_type_follows_the_place_of_supply() {
+        assert_eq!(state_code("Tamil Nadu"), Some("33"));
+        assert_eq!(state_code(" tamil nadu "), Some("33"));
+        assert_eq!(state_code("33"), Some("33"));
+        assert_eq!(supply_type("33", Some("33")), SupplyType::IntraState);
+        assert_eq!(supply_type("33", Some("29")), SupplyType::InterState);
+        assert_eq!(supply_type("33", None), SupplyType::InterState);
+    }
+}
@@ -0,0 +1,57 @@
+use mongodb::{
+    error::UNKNOWN_TRANSACTION_COMMIT_RESULT,
+    options::{Acknowledgment, ReadConcern, TransactionOptions, WriteConcern},
+    Client, ClientSession,
+};
+
+use crate::error::{Error, Result};
+
+/// Attempts at a transaction before a transient failure is reported.
+pub const MAX_ATTEMPTS: usize = 3;
+
+fn options() -> TransactionOptions {
+    TransactionOptions::builder()
+        .read_concern(ReadConcern::snapshot())
+        .write_concern(WriteConcern::builder().w(Acknowledgment::Majority).build())
+        .build()
+}
+
+/// Opens a session with a snapshot transaction started on it.
+pub async fn start(client: &Client) -> Result<ClientSession> {
+    let mut session = client.start_session(None).await?;
+    session.start_transaction(options()).await?;
+    Ok(session)
+}
+
+/// Commits the transaction of `session`, retrying while its outcome is unknown.
+pub async fn commit(session: &mut ClientSession) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await {
+            Err(err)
+                if err.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                    && attempt < MAX_ATTEMPTS =>
+            {
+                attempt += 1;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Commits `result` of the work done in `session`, or rolls the work back.
+pub async fn finish<T>(session: &mut ClientSession, result: Result<T>) -> Result<T> {
+    match result {
+        Ok(value) => commit(session).await.map(|_| value),
+        Err(err) => {
+            // The server may have aborted already; the original error is the one to report.
+            let _ = session.abort_transaction().await;
+            Err(err)
+        }
+    }
+}
+
+/// Whether attempt number `attempt` failed with `err` in a way worth running again.
+pub fn should_retry(err: &Error, attempt: usize) -> bool {
+    err.is_transient_transaction() && attempt < MAX_ATTEMPTS
+}
@@ -0,0 +1,253 @@
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client, ClientSession, Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::model::{Invoice, Organization, Payment, PaymentMode, WalletEntry, WalletEntryKind};
+use crate::money::Money;
+use crate::transaction;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopUpRequest {
+    /// Amount received, in paise.
+    pub amount: Money,
+    pub reference: Option<String>,
+}
+
+fn after_update() -> FindOneAndUpdateOptions {
+    FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build()
+}
+
+fn entry(
+    organization: &Organization,
+    kind: WalletEntryKind,
+    amount: Money,
+    invoice: Option<ObjectId>,
+    reference: Option<String>,
+) -> WalletEntry {
+    WalletEntry {
+        id: ObjectId::new(),
+        organization: organization.name.clone(),
+        kind,
+        amount,
+        balance: organization.wallet_balance,
+        invoice,
+        reference,
+        created_at: DateTime::now(),
+    }
+}
+
+fn opening_reference() -> Option<String> {
+    Some(String::from("Migrated from organization fund"))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalletStatement {
+    pub balance: Money,
+    pub entries: Vec<WalletEntry>,
+}
+
+/// Balance of the wallet of `organization`, counting a legacy `fund` that has
+/// not been moved into the ledger yet.
+pub fn balance(organization: &Organization) -> Money {
+    organization.wallet_balance + Money::from_rupees(organization.fund as i64)
+}
+
+/// Ledger of `organization`, newest entry first. Only reads; a legacy `fund` is
+/// reported in the balance and moved into the ledger by the next wallet write.
+pub async fn statement(db: &Database, organization: &Organization) -> Result<WalletStatement> {
+    let entries = WalletEntry::collection(db)
+        .find(
+            doc! {"organization": &organization.name},
+            FindOptions::builder().sort(doc! {"createdAt": -1}).build(),
+        )
+        .await?
+        .try_collect::<Vec<WalletEntry>>()
+        .await?;
+    Ok(WalletStatement {
+        balance: balance(organization),
+        entries,
+    })
+}
+
+/// Reads `organization` inside the transaction of `session`, first moving a legacy
+/// `fund` into the ledger as its opening balance. The fund is cleared by the same
+/// write, so it is opened exactly once.
+async fn open_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    organization: &Organization,
+) -> Result<Organization> {
+    let organization = Organization::collection(db)
+        .find_one_with_session(doc! {"_id": organization.id}, None, session)
+        .await?
+        .ok_or_else(|| Error::new("Organization not found", ErrorKind::NotFound))?;
+    if organization.fund == 0 {
+        return Ok(organization);
+    }
+    let amount = Money::from_rupees(organization.fund as i64);
+    let organization = Organization::collection(db)
+        .find_one_and_update_with_session(
+            doc! {"_id": organization.id, "fund": organization.fund as i64},
+            doc! {
+                "$inc": {"walletBalance": amount},
+                "$set": {"fund": 0_i64, "updatedAt": DateTime::now()},
+            },
+            after_update(),
+            session,
+        )
+        .await?
+        .ok_or_else(|| Error::new("Organization not found", ErrorKind::NotFound))?;
+    let opening = entry(
+        &organization,
+        WalletEntryKind::OpeningBalance,
+        amount,
+        None,
+        opening_reference(),
+    );
+    WalletEntry::collection(db)
+        .insert_one_with_session(&opening, None, session)
+        .await?;
+    Ok(organization)
+}
+
+/// Credits the wallet of `organization`. The balance and its ledger entry are
+/// written in one transaction, so no movement goes unrecorded.
+pub async fn top_up(
+    client: &Client,
+    db: &Database,
+    organization: &Organization,
+    request: TopUpRequest,
+) -> Result<WalletEntry> {
+    if !request.amount.is_positive() {
+        return Err(Error::new(
+            "Top-up amount must be positive",
+            ErrorKind::InvalidData,
+        ));
+    }
+    let mut attempt = 1;
+    loop {
+        let mut session = transaction::start(client).await?;
+        let result = top_up_with_session(db, &mut session, organization, &request).await;
+        match transaction::finish(&mut session, result).await {
+            Err(err) if transaction::should_retry(&err, attempt) => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn top_up_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    organization: &Organization,
+    request: &TopUpRequest,
+) -> Result<WalletEntry> {
+    let organization = open_with_session(db, session, organization).await?;
+    let organization = Organization::collection(db)
+        .find_one_and_update_with_session(
+            doc! {"_id": organization.id},
+            doc! {
+                "$inc": {"walletBalance": request.amount},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+            after_update(),
+            session,
+        )
+        .await?
+        .ok_or_else(|| Error::new("Organization not found", ErrorKind::NotFound))?;
+    let entry = entry(
+        &organization,
+        WalletEntryKind::TopUp,
+        request.amount,
+        None,
+        request.reference.clone(),
+    );
+    WalletEntry::collection(db)
+        .insert_one_with_session(&entry, None, session)
+        .await?;
+    Ok(entry)
+}
+
+/// Pays as much of the just finalized `invoice` as the wallet of `organization`
+/// covers, inside the billing transaction of `session`, so the debit, its ledger
+/// entry and the payment are written together with the finalized invoice or not
+/// at all. Returns the updated invoice.
+pub async fn apply_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    organization: &Organization,
+    invoice: &Invoice,
+) -> Result<Invoice> {
+    let organization = open_with_session(db, session, organization).await?;
+    let now = DateTime::now();
+    let amount = organization.wallet_balance.min(invoice.outstanding());
+    if !amount.is_positive() {
+        return Ok(invoice.clone());
+    }
+    // A concurrent top-up or debit conflicts with this write and retries the transaction.
+    let debited = Organization::collection(db)
+        .find_one_and_update_with_session(
+            doc! {"_id": organization.id, "walletBalance": {"$gte": amount}},
+            doc! {
+                "$inc": {"walletBalance": -amount},
+                "$set": {"updatedAt": now},
+            },
+            after_update(),
+            session,
+        )
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                "Wallet balance changed while it was applied",
+                ErrorKind::LogicalError,
+            )
+        })?;
+    let application = entry(
+        &debited,
+        WalletEntryKind::InvoiceApplication,
+        -amount,
+        Some(invoice.id),
+        invoice.invoice_no.clone(),
+    );
+    WalletEntry::collection(db)
+        .insert_one_with_session(&application, None, session)
+        .await?;
+    let payment = Payment {
+        id: ObjectId::new(),
+        invoice: invoice.id,
+        organization: invoice.organization.clone(),
+        amount,
+        mode: PaymentMode::Wallet,
+        reference: Some(application.id.to_hex()),
+        date: now,
+        created_at: now,
+    };
+    Payment::collection(db)
+        .insert_one_with_session(&payment, None, session)
+        .await?;
+    let mut invoice = invoice.clone();
+    invoice.amount_paid += amount;
+    invoice.paid_status = invoice.settled_status();
+    invoice.updated_at = now;
+    Invoice::collection(db)
+        .update_one_with_session(
+            doc! {"_id": invoice.id},
+            doc! {"$set": {
+                "amountPaid": invoice.amount_paid,
+                "paidStatus": invoice.paid_status,
+                "updatedAt": now,
+            }},
+            None,
+            session,
+        )
+        .await?;
+    Ok(invoice)
+}